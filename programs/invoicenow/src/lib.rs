@@ -1,5 +1,11 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::token::{self, Approve, CloseAccount, Mint, Revoke, Token, TokenAccount, Transfer};
 
 declare_id!("GyR2tNwj8UF4AUpiUjzXKqW9mdHcgQzuByqnyhGk6s3N");
 
@@ -9,11 +15,22 @@ const MAX_WIN_PCT_BPS: u16 = 1000; // 10% max single win as % of pool
 const MIN_POOL_RESERVE_BPS: u16 = 2000; // 20% min reserve
 const BPS_DIVISOR: u64 = 10000;
 
+// Constants for invoices
+const MAX_MEMO_LEN: usize = 256;
+const MAX_MILESTONES: usize = 64;
+const MAX_MILESTONE_DESCRIPTION_LEN: usize = 128;
+const MAX_DELIVERABLE_URI_LEN: usize = 200;
+const MAX_ARBITER_FEE_BPS: u16 = 1000; // 10% max arbiter fee
+const MAX_RETAINAGE_BPS: u16 = 5000; // 50% max held back per release
+
+const MAX_YIELD_PROGRAMS: usize = 16;
+
 #[program]
 pub mod invoicenow {
     use super::*;
 
     /// Create a new invoice
+    #[allow(clippy::too_many_arguments)]
     pub fn create_invoice(
         ctx: Context<CreateInvoice>,
         invoice_id: String,
@@ -22,16 +39,78 @@ pub mod invoicenow {
         due_date: i64,
         memo: String,
         milestones: Vec<Milestone>,
+        terms: InvoiceTerms,
     ) -> Result<()> {
         let invoice = &mut ctx.accounts.invoice;
         let clock = Clock::get()?;
 
         require!(invoice_id.len() <= 32, InvoiceError::InvoiceIdTooLong);
-        require!(memo.len() <= 256, InvoiceError::MemoTooLong);
-        require!(milestones.len() <= 10, InvoiceError::TooManyMilestones);
+        require!(memo.len() <= MAX_MEMO_LEN, InvoiceError::MemoTooLong);
+        require!(milestones.len() <= MAX_MILESTONES, InvoiceError::TooManyMilestones);
+        require!(terms.review_period >= 0, InvoiceError::InvalidReviewPeriod);
+        // Only a designated client can accept, so open invoices can't require it
+        require!(
+            terms.client.is_some() || !terms.require_acceptance,
+            InvoiceError::NoDesignatedClient
+        );
+        require!(
+            terms.delivery_deadline >= 0,
+            InvoiceError::InvalidDeliveryDeadline
+        );
+        require!(
+            terms.retainage_bps <= MAX_RETAINAGE_BPS && terms.warranty_period >= 0,
+            InvoiceError::InvalidRetainage
+        );
+        require!(
+            terms.arbiter_fee_bps <= MAX_ARBITER_FEE_BPS,
+            InvoiceError::ArbiterFeeTooHigh
+        );
+        require!(
+            terms.arbiter.is_some() || terms.arbiter_fee_bps == 0,
+            InvoiceError::NoArbiter
+        );
+        if let Some(arbiter) = terms.arbiter {
+            require!(
+                arbiter != ctx.accounts.creator.key() && Some(arbiter) != terms.client,
+                InvoiceError::InvalidArbiter
+            );
+        }
+        require!(terms.hold_period >= 0, InvoiceError::InvalidHoldPeriod);
+        // Chargebacks the creator contests need someone to settle them
+        require!(
+            terms.arbiter.is_some() || terms.hold_period == 0,
+            InvoiceError::NoArbiter
+        );
+        require!(
+            terms.vesting_cliff >= 0
+                && terms.vesting_cliff <= terms.vesting_duration
+                && (terms.vesting_duration == 0 || terms.hold_period == 0),
+            InvoiceError::InvalidVestingSchedule
+        );
+        require!(
+            terms.yield_creator_bps as u64 <= BPS_DIVISOR,
+            InvoiceError::InvalidYieldSplit
+        );
+        if let Some(yield_program) = terms.yield_program {
+            require!(
+                ctx.accounts
+                    .yield_config
+                    .as_ref()
+                    .is_some_and(|config| config.programs.contains(&yield_program)),
+                InvoiceError::YieldProgramNotWhitelisted
+            );
+        } else {
+            require!(terms.yield_creator_bps == 0, InvoiceError::NoYieldStrategy);
+        }
+        validate_milestones(&milestones)?;
+        require!(
+            milestones_total(&milestones)? <= amount,
+            InvoiceError::MilestoneTotalMismatch
+        );
 
         invoice.creator = ctx.accounts.creator.key();
-        invoice.client = Pubkey::default(); // Set when client pays or escrow is funded
+        // Open invoices get their client set when paid, accepted or escrow is funded
+        invoice.client = terms.client.unwrap_or_default();
         invoice.invoice_id = invoice_id;
         invoice.amount = amount;
         invoice.token_mint = token_mint;
@@ -44,6 +123,25 @@ pub mod invoicenow {
         invoice.current_milestone = 0;
         invoice.escrow_funded = false;
         invoice.bump = ctx.bumps.invoice;
+        invoice.audit_hash = [0u8; 32];
+        invoice.audit_count = 0;
+        invoice.require_acceptance = terms.require_acceptance;
+        invoice.rejection_reason = 0;
+        invoice.version = Invoice::VERSION;
+        invoice.review_period = terms.review_period;
+        invoice.arbiter = terms.arbiter.unwrap_or_default();
+        invoice.arbiter_fee_bps = terms.arbiter_fee_bps;
+        invoice.delivery_deadline = terms.delivery_deadline;
+        invoice.retainage_bps = terms.retainage_bps;
+        invoice.warranty_period = terms.warranty_period;
+        invoice.retainage_held = 0;
+        invoice.yield_program = terms.yield_program.unwrap_or_default();
+        invoice.yield_creator_bps = terms.yield_creator_bps;
+        invoice.yield_deposited = 0;
+        invoice.hold_period = terms.hold_period;
+        invoice.vesting_cliff = terms.vesting_cliff;
+        invoice.vesting_duration = terms.vesting_duration;
+        invoice.legacy_escrow = false;
 
         emit!(InvoiceCreated {
             invoice_key: invoice.key(),
@@ -56,20 +154,80 @@ pub mod invoicenow {
         Ok(())
     }
 
-    /// Fund escrow for milestone-based invoice
-    pub fn fund_escrow(ctx: Context<FundEscrow>, amount: u64) -> Result<()> {
+    /// Replace the memo of an unpaid invoice, resizing the account to fit
+    pub fn update_memo(ctx: Context<UpdateMemo>, memo: String) -> Result<()> {
         let invoice = &mut ctx.accounts.invoice;
-        let escrow = &mut ctx.accounts.escrow;
 
         require!(
             invoice.status == InvoiceStatus::Pending,
             InvoiceError::InvalidInvoiceStatus
         );
-        require!(amount >= invoice.amount, InvoiceError::InsufficientFunding);
+        require!(memo.len() <= MAX_MEMO_LEN, InvoiceError::MemoTooLong);
+
+        invoice.memo = memo;
+
+        emit!(InvoiceUpdated {
+            invoice_key: invoice.key(),
+            milestone_count: invoice.milestones.len() as u8,
+        });
+
+        Ok(())
+    }
+
+    /// Append milestones to an unpaid invoice, growing the account to fit
+    pub fn add_milestones(ctx: Context<AddMilestones>, milestones: Vec<Milestone>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+
+        require!(
+            invoice.status == InvoiceStatus::Pending,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            invoice.milestones.len() + milestones.len() <= MAX_MILESTONES,
+            InvoiceError::TooManyMilestones
+        );
+        require!(invoice.share_count == 0, InvoiceError::SplitInvoice);
+        validate_milestones(&milestones)?;
+
+        invoice.milestones.extend(milestones);
+        require!(
+            milestones_total(&invoice.milestones)? <= invoice.amount,
+            InvoiceError::MilestoneTotalMismatch
+        );
+
+        emit!(InvoiceUpdated {
+            invoice_key: invoice.key(),
+            milestone_count: invoice.milestones.len() as u8,
+        });
+
+        Ok(())
+    }
+
+    /// Fund escrow for milestone-based invoice, in full or as a first tranche
+    /// topped up later with `top_up_escrow`
+    pub fn fund_escrow(ctx: Context<FundEscrow>, amount: u64) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let escrow = &mut ctx.accounts.escrow;
+        let clock = Clock::get()?;
+
+        invoice.check_payable(ctx.accounts.client.key())?;
+        // Chargeback and vesting terms only hold if the invoice is paid that way
+        require!(invoice.hold_period == 0, InvoiceError::HoldRequired);
+        require!(invoice.vesting_duration == 0, InvoiceError::VestingRequired);
+        require!(amount > 0, InvoiceError::InvalidAmount);
         require!(!invoice.milestones.is_empty(), InvoiceError::NoMilestones);
+        require!(
+            milestones_total(&invoice.milestones)? == invoice.amount,
+            InvoiceError::MilestoneTotalMismatch
+        );
+
+        // Only up to the invoice amount is pulled; anything above it stays with the client
+        let deposit = amount.min(invoice.amount);
 
         // Initialize escrow
-        escrow.invoice_id = invoice.invoice_id.clone();
+        escrow.invoice = invoice.key();
+        escrow.deposited = deposit;
+        escrow.released = 0;
         escrow.bump = ctx.bumps.escrow;
 
         // Transfer tokens from client to escrow
@@ -81,932 +239,5579 @@ pub mod invoicenow {
                 authority: ctx.accounts.client.to_account_info(),
             },
         );
-        token::transfer(transfer_ctx, amount)?;
+        token::transfer(transfer_ctx, deposit)?;
 
         invoice.client = ctx.accounts.client.key();
         invoice.escrow_funded = true;
-        invoice.status = InvoiceStatus::EscrowFunded;
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::EscrowFunded,
+            ctx.accounts.client.key(),
+            clock.unix_timestamp,
+        );
 
         emit!(EscrowFunded {
-            invoice_key: invoice.key(),
+            invoice_key,
             client: ctx.accounts.client.key(),
-            amount,
+            amount: deposit,
         });
+        if let Some(event) = invoice.funding_required(invoice_key, escrow) {
+            emit!(event);
+        }
 
         Ok(())
     }
 
-    /// Release funds for a completed milestone
-    pub fn release_milestone(ctx: Context<ReleaseMilestone>) -> Result<()> {
-        let invoice = &mut ctx.accounts.invoice;
-        let clock = Clock::get()?;
+    /// Deposit a further tranche into a partially funded escrow (client only)
+    pub fn top_up_escrow(ctx: Context<TopUpEscrow>, amount: u64) -> Result<()> {
+        let invoice = &ctx.accounts.invoice;
+        let escrow = &mut ctx.accounts.escrow;
+        let invoice_key = invoice.key();
 
         require!(
             invoice.status == InvoiceStatus::EscrowFunded,
             InvoiceError::InvalidInvoiceStatus
         );
-        require!(invoice.escrow_funded, InvoiceError::EscrowNotFunded);
-        require!(
-            (invoice.current_milestone as usize) < invoice.milestones.len(),
-            InvoiceError::AllMilestonesComplete
-        );
-
-        // Only creator or client can release milestone
-        require!(
-            ctx.accounts.authority.key() == invoice.creator
-                || ctx.accounts.authority.key() == invoice.client,
-            InvoiceError::Unauthorized
-        );
-
-        let milestone_idx = invoice.current_milestone as usize;
-        let milestone_amount = invoice.milestones[milestone_idx].amount;
+        require!(amount > 0, InvoiceError::InvalidAmount);
 
-        // Transfer from escrow to creator
-        let invoice_id = invoice.invoice_id.clone();
-        let seeds = &[
-            b"escrow",
-            invoice_id.as_bytes(),
-            &[ctx.accounts.escrow.bump],
-        ];
-        let signer_seeds = &[&seeds[..]];
+        let outstanding = invoice.amount.saturating_sub(escrow.deposited);
+        require!(outstanding > 0, InvoiceError::EscrowFullyFunded);
+        let deposit = amount.min(outstanding);
 
-        let transfer_ctx = CpiContext::new_with_signer(
+        let transfer_ctx = CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
             Transfer {
-                from: ctx.accounts.escrow_token_account.to_account_info(),
-                to: ctx.accounts.creator_token_account.to_account_info(),
-                authority: ctx.accounts.escrow.to_account_info(),
+                from: ctx.accounts.client_token_account.to_account_info(),
+                to: ctx.accounts.escrow_token_account.to_account_info(),
+                authority: ctx.accounts.client.to_account_info(),
             },
-            signer_seeds,
         );
-        token::transfer(transfer_ctx, milestone_amount)?;
+        token::transfer(transfer_ctx, deposit)?;
 
-        // Update milestone status
-        invoice.milestones[milestone_idx].completed = true;
-        invoice.milestones[milestone_idx].completed_at = clock.unix_timestamp;
-        invoice.current_milestone += 1;
+        escrow.deposited = escrow.deposited.checked_add(deposit).unwrap();
 
-        // Check if all milestones complete
-        if invoice.current_milestone as usize >= invoice.milestones.len() {
-            invoice.status = InvoiceStatus::Paid;
-            invoice.paid_at = clock.unix_timestamp;
+        emit!(EscrowFunded {
+            invoice_key,
+            client: ctx.accounts.client.key(),
+            amount: deposit,
+        });
+        if let Some(event) = invoice.funding_required(invoice_key, escrow) {
+            emit!(event);
         }
 
-        emit!(MilestoneReleased {
-            invoice_key: invoice.key(),
-            milestone_index: milestone_idx as u8,
-            amount: milestone_amount,
-        });
+        Ok(())
+    }
+
+    /// Contribute to another client's escrow, recording the share in a
+    /// `Contribution` so refunds can be returned pro-rata. Later deposits go
+    /// through `top_up_contribution`.
+    pub fn contribute_escrow(ctx: Context<ContributeEscrow>, amount: u64) -> Result<()> {
+        let contribution = &mut ctx.accounts.contribution;
+        contribution.invoice = ctx.accounts.invoice.key();
+        contribution.contributor = ctx.accounts.contributor.key();
+        contribution.amount = 0;
+        contribution.bump = ctx.bumps.contribution;
+        ctx.accounts.invoice.contributor_count += 1;
+
+        deposit_contribution(
+            &mut ctx.accounts.invoice,
+            &mut ctx.accounts.escrow,
+            &ctx.accounts.escrow_token_account,
+            contribution,
+            &ctx.accounts.contributor_token_account,
+            &ctx.accounts.contributor,
+            &ctx.accounts.token_program,
+            amount,
+        )
+    }
+
+    /// Add to an existing contribution
+    pub fn top_up_contribution(ctx: Context<TopUpContribution>, amount: u64) -> Result<()> {
+        deposit_contribution(
+            &mut ctx.accounts.invoice,
+            &mut ctx.accounts.escrow,
+            &ctx.accounts.escrow_token_account,
+            &mut ctx.accounts.contribution,
+            &ctx.accounts.contributor_token_account,
+            &ctx.accounts.contributor,
+            &ctx.accounts.token_program,
+            amount,
+        )
+    }
+
+    /// Close a contribution that can no longer be refunded, returning its rent
+    /// to the contributor
+    pub fn close_contribution(ctx: Context<CloseContribution>) -> Result<()> {
+        let invoice = &ctx.accounts.invoice;
+
+        // Paid retainage can still be disputed and refunded to contributors
+        let settled = match invoice.status {
+            InvoiceStatus::Paid => invoice.retainage_held == 0,
+            InvoiceStatus::Cancelled | InvoiceStatus::Resolved => {
+                invoice.refunds_outstanding == 0
+            }
+            _ => false,
+        };
+        require!(settled, InvoiceError::ContributionRefundable);
 
         Ok(())
     }
 
-    /// Mark invoice as paid (for direct payments without escrow)
-    pub fn mark_paid(ctx: Context<MarkPaid>, tx_signature: String) -> Result<()> {
+    /// Claim a contributor's pro-rata share of a refunded escrow
+    pub fn claim_refund(ctx: Context<ClaimRefund>) -> Result<()> {
         let invoice = &mut ctx.accounts.invoice;
-        let clock = Clock::get()?;
+        let contribution = &ctx.accounts.contribution;
 
         require!(
-            invoice.status == InvoiceStatus::Pending,
+            matches!(
+                invoice.status,
+                InvoiceStatus::Cancelled | InvoiceStatus::Resolved
+            ),
             InvoiceError::InvalidInvoiceStatus
         );
-        require!(tx_signature.len() <= 88, InvoiceError::TxSignatureTooLong);
+        require!(invoice.refunds_outstanding > 0, InvoiceError::NothingToClaim);
 
-        invoice.status = InvoiceStatus::Paid;
-        invoice.paid_at = clock.unix_timestamp;
-        invoice.client = ctx.accounts.payer.key();
+        // The last claimant also sweeps any rounding dust
+        let amount = if invoice.refunds_outstanding == 1 {
+            ctx.accounts.escrow_token_account.amount
+        } else {
+            pro_rata(invoice.refund_pool, contribution.amount, invoice.contributed)
+        };
 
-        emit!(InvoicePaid {
-            invoice_key: invoice.key(),
-            payer: ctx.accounts.payer.key(),
-            tx_signature,
-            paid_at: clock.unix_timestamp,
+        let invoice_key = invoice.key();
+        let seeds = &[b"escrow", invoice_key.as_ref(), &[ctx.accounts.escrow.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        if amount > 0 {
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    to: ctx.accounts.contributor_token_account.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token::transfer(transfer_ctx, amount)?;
+        }
+
+        invoice.refunds_outstanding -= 1;
+        if invoice.refunds_outstanding == 0 {
+            close_escrow(
+                &ctx.accounts.token_program,
+                &ctx.accounts.escrow,
+                &ctx.accounts.escrow_token_account,
+                ctx.accounts.client.to_account_info(),
+            )?;
+        }
+
+        emit!(RefundClaimed {
+            invoice_key,
+            contributor: contribution.contributor,
+            amount,
         });
 
         Ok(())
     }
 
-    /// Cancel an unpaid invoice
-    pub fn cancel_invoice(ctx: Context<CancelInvoice>) -> Result<()> {
-        let invoice = &mut ctx.accounts.invoice;
+    /// Release funds for a completed milestone, in any order. Releasing is the
+    /// client's approval; unapproved milestones go through `auto_release_milestone`.
+    pub fn release_milestone<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReleaseMilestone<'info>>,
+        milestone_index: u8,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let milestone_idx = milestone_index as usize;
 
+        ctx.accounts.check_releasable(milestone_idx)?;
         require!(
-            invoice.status == InvoiceStatus::Pending,
-            InvoiceError::InvalidInvoiceStatus
-        );
-        require!(
-            ctx.accounts.creator.key() == invoice.creator,
+            ctx.accounts.authority.key() == ctx.accounts.invoice.client,
             InvoiceError::Unauthorized
         );
+        ctx.accounts.recall_deployed(ctx.remaining_accounts)?;
 
-        invoice.status = InvoiceStatus::Cancelled;
+        let amount = ctx.accounts.invoice.milestones[milestone_idx].unreleased();
+        ctx.accounts.pay_milestone(milestone_idx, amount, clock.unix_timestamp)?;
 
-        emit!(InvoiceCancelled {
-            invoice_key: invoice.key(),
+        emit!(MilestoneReleased {
+            invoice_key: ctx.accounts.invoice.key(),
+            milestone_index,
+            amount,
         });
 
         Ok(())
     }
 
-    /// Create user profile
-    pub fn create_profile(
-        ctx: Context<CreateProfile>,
-        name: String,
-        email: String,
-        business_name: Option<String>,
+    /// Release part of a milestone, by amount or basis points of the
+    /// milestone (client only)
+    pub fn release_partial<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReleaseMilestone<'info>>,
+        milestone_index: u8,
+        release: ReleaseAmount,
     ) -> Result<()> {
-        let profile = &mut ctx.accounts.profile;
+        let clock = Clock::get()?;
+        let milestone_idx = milestone_index as usize;
 
-        require!(name.len() <= 64, InvoiceError::NameTooLong);
-        require!(email.len() <= 128, InvoiceError::EmailTooLong);
+        ctx.accounts.check_releasable(milestone_idx)?;
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.invoice.client,
+            InvoiceError::Unauthorized
+        );
+        ctx.accounts.recall_deployed(ctx.remaining_accounts)?;
+
+        let milestone = &ctx.accounts.invoice.milestones[milestone_idx];
+        let amount = match release {
+            ReleaseAmount::Amount(amount) => amount,
+            ReleaseAmount::Bps(bps) => {
+                require!(bps as u64 <= BPS_DIVISOR, InvoiceError::InvalidReleaseAmount);
+                milestone
+                    .amount
+                    .checked_mul(bps as u64)
+                    .unwrap()
+                    .checked_div(BPS_DIVISOR)
+                    .unwrap()
+            }
+        };
+        require!(
+            amount > 0 && amount <= milestone.unreleased(),
+            InvoiceError::InvalidReleaseAmount
+        );
 
-        profile.wallet = ctx.accounts.owner.key();
-        profile.name = name;
-        profile.email = email;
-        profile.business_name = business_name.unwrap_or_default();
-        profile.total_invoices = 0;
-        profile.total_received = 0;
-        profile.bump = ctx.bumps.profile;
+        ctx.accounts.pay_milestone(milestone_idx, amount, clock.unix_timestamp)?;
+
+        emit!(MilestonePartiallyReleased {
+            invoice_key: ctx.accounts.invoice.key(),
+            milestone_index,
+            amount,
+            amount_released: ctx.accounts.invoice.milestones[milestone_idx].amount_released,
+        });
 
         Ok(())
     }
 
-    // ============== LOTTERY INSTRUCTIONS ==============
-
-    /// Initialize a lottery pool for a specific token
-    pub fn initialize_lottery_pool(
-        ctx: Context<InitializeLotteryPool>,
-        house_edge_bps: u16,
-        min_pool_reserve_bps: u16,
-        max_win_pct_bps: u16,
+    /// Release a submitted milestone once its review deadline passed without
+    /// objection (permissionless crank)
+    pub fn auto_release_milestone<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReleaseMilestone<'info>>,
+        milestone_index: u8,
     ) -> Result<()> {
-        require!(house_edge_bps <= MAX_HOUSE_EDGE_BPS, InvoiceError::HouseEdgeTooHigh);
-        require!(min_pool_reserve_bps <= 5000, InvoiceError::ReserveTooHigh);
-        require!(max_win_pct_bps <= MAX_WIN_PCT_BPS, InvoiceError::MaxWinTooHigh);
+        let clock = Clock::get()?;
+        let milestone_idx = milestone_index as usize;
 
-        let pool = &mut ctx.accounts.lottery_pool;
-        pool.authority = ctx.accounts.authority.key();
-        pool.token_mint = ctx.accounts.token_mint.key();
-        pool.total_balance = 0;
-        pool.total_premiums_collected = 0;
-        pool.total_payouts = 0;
-        pool.total_entries = 0;
-        pool.total_wins = 0;
-        pool.house_edge_bps = house_edge_bps;
-        pool.min_pool_reserve_bps = min_pool_reserve_bps;
-        pool.max_win_pct_bps = max_win_pct_bps;
-        pool.paused = false;
-        pool.bump = ctx.bumps.lottery_pool;
+        ctx.accounts.check_releasable(milestone_idx)?;
 
-        emit!(LotteryPoolCreated {
-            pool: pool.key(),
-            token_mint: pool.token_mint,
-            house_edge_bps,
+        let milestone = &ctx.accounts.invoice.milestones[milestone_idx];
+        require!(milestone.submitted_at > 0, InvoiceError::MilestoneNotSubmitted);
+        require!(
+            milestone.review_deadline > 0 && clock.unix_timestamp >= milestone.review_deadline,
+            InvoiceError::ReviewPeriodActive
+        );
+
+        let amount = milestone.unreleased();
+        ctx.accounts.recall_deployed(ctx.remaining_accounts)?;
+        ctx.accounts.pay_milestone(milestone_idx, amount, clock.unix_timestamp)?;
+
+        emit!(MilestoneAutoReleased {
+            invoice_key: ctx.accounts.invoice.key(),
+            milestone_index,
+            amount,
+            released_by: ctx.accounts.authority.key(),
         });
 
         Ok(())
     }
 
-    /// Seed the lottery pool with initial funds
-    pub fn seed_lottery_pool(ctx: Context<SeedLotteryPool>, amount: u64) -> Result<()> {
-        let pool = &mut ctx.accounts.lottery_pool;
-
-        require!(!pool.paused, InvoiceError::PoolPaused);
-        require!(amount > 0, InvoiceError::InvalidAmount);
+    /// Pay out retainage held back from milestone releases, on the client's
+    /// final acceptance or by anyone once the warranty period has passed
+    pub fn release_retainage(ctx: Context<ReleaseMilestone>) -> Result<()> {
+        let invoice = &ctx.accounts.invoice;
+        let clock = Clock::get()?;
 
-        // Transfer tokens from seeder to pool vault
-        let transfer_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.seeder_token_account.to_account_info(),
-                to: ctx.accounts.pool_vault.to_account_info(),
-                authority: ctx.accounts.seeder.to_account_info(),
-            },
+        require!(
+            invoice.status == InvoiceStatus::Paid,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(invoice.retainage_held > 0, InvoiceError::NoRetainageHeld);
+        require!(
+            ctx.accounts.authority.key() == invoice.client
+                || clock.unix_timestamp >= invoice.paid_at.saturating_add(invoice.warranty_period),
+            InvoiceError::WarrantyPeriodActive
         );
-        token::transfer(transfer_ctx, amount)?;
 
-        pool.total_balance = pool.total_balance.checked_add(amount).unwrap();
+        let amount = invoice.retainage_held;
+        ctx.accounts.pay_creator(amount)?;
+        ctx.accounts.invoice.retainage_held = 0;
 
-        emit!(LotteryPoolSeeded {
-            pool: pool.key(),
+        emit!(RetainageReleased {
+            invoice_key: ctx.accounts.invoice.key(),
             amount,
-            new_balance: pool.total_balance,
+            released_by: ctx.accounts.authority.key(),
         });
 
         Ok(())
     }
 
-    /// Pay invoice with lottery option (premium for chance to win)
-    pub fn pay_with_lottery(
-        ctx: Context<PayWithLottery>,
-        premium_amount: u64,
+    /// Submit a milestone for client review (creator only)
+    pub fn submit_milestone(
+        ctx: Context<SubmitMilestone>,
+        milestone_index: u8,
+        deliverable_hash: Option<[u8; 32]>,
+        deliverable_uri: String,
     ) -> Result<()> {
-        let pool = &mut ctx.accounts.lottery_pool;
-        let invoice = &ctx.accounts.invoice;
-        let entry = &mut ctx.accounts.lottery_entry;
+        let invoice = &mut ctx.accounts.invoice;
+        let invoice_key = invoice.key();
+        let review_period = invoice.review_period;
         let clock = Clock::get()?;
 
-        // Validations
-        require!(!pool.paused, InvoiceError::PoolPaused);
-        require!(invoice.status == InvoiceStatus::Pending, InvoiceError::InvalidInvoiceStatus);
-        require!(premium_amount > 0, InvoiceError::InvalidAmount);
-
-        // Invoice must be at least 5 minutes old (prevent gaming)
         require!(
-            clock.unix_timestamp - invoice.created_at >= 300,
-            InvoiceError::InvoiceTooNew
+            invoice.status == InvoiceStatus::EscrowFunded,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            deliverable_uri.len() <= MAX_DELIVERABLE_URI_LEN,
+            InvoiceError::DeliverableUriTooLong
         );
 
-        let invoice_amount = invoice.amount;
-
-        // Calculate max win based on pool balance
-        let available_pool = pool.total_balance
-            .saturating_mul(BPS_DIVISOR - pool.min_pool_reserve_bps as u64)
-            / BPS_DIVISOR;
-        let max_win = available_pool
-            .saturating_mul(pool.max_win_pct_bps as u64)
-            / BPS_DIVISOR;
-
-        require!(invoice_amount <= max_win, InvoiceError::InvoiceExceedsMaxWin);
-
-        // Calculate win probability
-        // Formula: win_prob = premium / (invoice_amount * (1 + house_edge))
-        let house_edge_multiplier = BPS_DIVISOR + pool.house_edge_bps as u64;
-        let effective_invoice = invoice_amount
-            .checked_mul(house_edge_multiplier)
-            .unwrap()
-            / BPS_DIVISOR;
+        let milestone = invoice
+            .milestones
+            .get_mut(milestone_index as usize)
+            .ok_or(InvoiceError::InvalidMilestoneIndex)?;
+        require!(!milestone.completed, InvoiceError::MilestoneAlreadyCompleted);
+        require!(milestone.submitted_at == 0, InvoiceError::MilestoneAlreadySubmitted);
+
+        milestone.submitted_at = clock.unix_timestamp;
+        milestone.deliverable_hash = deliverable_hash.unwrap_or_default();
+        milestone.deliverable_uri = deliverable_uri;
+        milestone.review_deadline = if review_period > 0 {
+            clock.unix_timestamp.saturating_add(review_period)
+        } else {
+            0
+        };
+
+        emit!(MilestoneSubmitted {
+            invoice_key,
+            milestone_index,
+            deliverable_hash: milestone.deliverable_hash,
+            deliverable_uri: milestone.deliverable_uri.clone(),
+            submitted_at: milestone.submitted_at,
+            review_deadline: milestone.review_deadline,
+        });
 
-        let win_probability_bps = (premium_amount as u128)
-            .checked_mul(BPS_DIVISOR as u128)
-            .unwrap()
-            .checked_div(effective_invoice as u128)
-            .unwrap_or(0) as u16;
+        Ok(())
+    }
 
-        // Cap probability at 95%
-        let win_probability_bps = win_probability_bps.min(9500);
+    /// Object to a submitted milestone, sending it back to the creator (client only)
+    pub fn object_milestone(ctx: Context<UpdateMilestone>, milestone_index: u8) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
 
-        // Transfer total payment (invoice + premium) from client to pool vault
-        let total_payment = invoice_amount.checked_add(premium_amount).unwrap();
-        let transfer_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.client_token_account.to_account_info(),
-                to: ctx.accounts.pool_vault.to_account_info(),
-                authority: ctx.accounts.client.to_account_info(),
-            },
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            ctx.accounts.authority.key() == invoice.client,
+            InvoiceError::Unauthorized
         );
-        token::transfer(transfer_ctx, total_payment)?;
 
-        // Update pool balance (add premium only, invoice amount held for settlement)
-        pool.total_balance = pool.total_balance.checked_add(premium_amount).unwrap();
-        pool.total_premiums_collected = pool.total_premiums_collected.checked_add(premium_amount).unwrap();
-        pool.total_entries = pool.total_entries.checked_add(1).unwrap();
+        let milestone = invoice
+            .milestones
+            .get_mut(milestone_index as usize)
+            .ok_or(InvoiceError::InvalidMilestoneIndex)?;
+        require!(!milestone.completed, InvoiceError::MilestoneAlreadyCompleted);
+        require!(milestone.submitted_at > 0, InvoiceError::MilestoneNotSubmitted);
 
-        // Create lottery entry
-        entry.invoice = invoice.key();
-        entry.client = ctx.accounts.client.key();
-        entry.invoice_amount = invoice_amount;
-        entry.premium_paid = premium_amount;
-        entry.win_probability_bps = win_probability_bps;
-        entry.status = LotteryStatus::PendingVrf;
-        entry.random_result = None;
-        entry.created_at = clock.unix_timestamp;
-        entry.resolved_at = 0;
-        entry.bump = ctx.bumps.lottery_entry;
+        milestone.submitted_at = 0;
+        milestone.review_deadline = 0;
 
-        emit!(LotteryEntryCreated {
-            entry: entry.key(),
-            invoice: invoice.key(),
-            client: ctx.accounts.client.key(),
-            invoice_amount,
-            premium_paid: premium_amount,
-            win_probability_bps,
+        emit!(MilestoneObjected {
+            invoice_key: invoice.key(),
+            milestone_index,
         });
 
         Ok(())
     }
 
-    /// Settle lottery result (called with randomness - simplified without VRF for hackathon)
-    pub fn settle_lottery(
-        ctx: Context<SettleLottery>,
-        random_bytes: [u8; 32],
+    /// Propose replacing the unfinished milestones of a funded escrow (either
+    /// party). A client raising the total deposits the difference up front.
+    pub fn propose_change_order(
+        ctx: Context<ProposeChangeOrder>,
+        milestones: Vec<Milestone>,
     ) -> Result<()> {
-        let pool = &mut ctx.accounts.lottery_pool;
-        let invoice = &mut ctx.accounts.invoice;
-        let entry = &mut ctx.accounts.lottery_entry;
-        let clock = Clock::get()?;
-
-        require!(entry.status == LotteryStatus::PendingVrf, InvoiceError::LotteryAlreadySettled);
+        let invoice = &ctx.accounts.invoice;
+        let proposer = ctx.accounts.proposer.key();
 
-        // Derive randomness (0-9999)
-        let random_value = u16::from_le_bytes([random_bytes[0], random_bytes[1]]) % 10000;
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            proposer == invoice.creator || proposer == invoice.client,
+            InvoiceError::Unauthorized
+        );
+        // Dropping every open milestone is a cancellation, not a change order
+        require!(!milestones.is_empty(), InvoiceError::InvalidMilestone);
+        validate_milestones(&milestones)?;
+
+        let amended = invoice.amended_milestones(&milestones);
+        require!(amended.len() <= MAX_MILESTONES, InvoiceError::TooManyMilestones);
+        let new_amount = milestones_total(&amended)?;
+
+        let mut deposited = 0;
+        if proposer == invoice.client && new_amount > invoice.amount {
+            deposited = new_amount - invoice.amount;
+            let transfer_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.client_token_account.to_account_info(),
+                    to: ctx.accounts.change_order_vault.to_account_info(),
+                    authority: ctx.accounts.proposer.to_account_info(),
+                },
+            );
+            token::transfer(transfer_ctx, deposited)?;
+        }
 
-        // Determine win/loss
-        let won = random_value < entry.win_probability_bps;
+        let change_order = &mut ctx.accounts.change_order;
+        change_order.invoice = invoice.key();
+        change_order.proposer = proposer;
+        change_order.milestones = milestones;
+        change_order.new_amount = new_amount;
+        change_order.deposited = deposited;
+        change_order.escrow_released = ctx.accounts.escrow.released;
+        change_order.bump = ctx.bumps.change_order;
 
-        entry.random_result = Some(random_bytes);
-        entry.resolved_at = clock.unix_timestamp;
+        emit!(ChangeOrderProposed {
+            invoice_key: invoice.key(),
+            proposer,
+            milestone_count: change_order.milestones.len() as u8,
+            old_amount: invoice.amount,
+            new_amount,
+        });
 
-        let token_mint = pool.token_mint;
-        let pool_bump = pool.bump;
+        Ok(())
+    }
 
-        if won {
-            // WIN: Refund invoice amount from pool to client
-            entry.status = LotteryStatus::Won;
-            pool.total_wins = pool.total_wins.checked_add(1).unwrap();
-            pool.total_payouts = pool.total_payouts.checked_add(entry.invoice_amount).unwrap();
+    /// Apply a change order (the party that did not propose it), topping up
+    /// or refunding the escrow so it matches the new total
+    pub fn accept_change_order(ctx: Context<AcceptChangeOrder>) -> Result<()> {
+        let invoice = &ctx.accounts.invoice;
+        let invoice_key = invoice.key();
+        let change_order = &ctx.accounts.change_order;
+        let authority = ctx.accounts.authority.key();
 
-            // Transfer invoice amount back to client (they won!)
-            let seeds = &[
-                b"lottery_pool",
-                token_mint.as_ref(),
-                &[pool_bump],
-            ];
-            let signer_seeds = &[&seeds[..]];
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            (authority == invoice.creator || authority == invoice.client)
+                && authority != change_order.proposer,
+            InvoiceError::Unauthorized
+        );
+        // Releases since the proposal would change which milestones are locked
+        require!(
+            ctx.accounts.escrow.released == change_order.escrow_released,
+            InvoiceError::ChangeOrderStale
+        );
 
-            let transfer_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.pool_vault.to_account_info(),
-                    to: ctx.accounts.client_token_account.to_account_info(),
-                    authority: pool.to_account_info(),
-                },
-                signer_seeds,
-            );
-            token::transfer(transfer_ctx, entry.invoice_amount)?;
+        let old_amount = invoice.amount;
+        let new_amount = change_order.new_amount;
+
+        release_change_order_vault(
+            &ctx.accounts.token_program,
+            change_order,
+            &ctx.accounts.change_order_vault,
+            ctx.accounts.escrow_token_account.to_account_info(),
+            ctx.accounts.proposer.to_account_info(),
+        )?;
+
+        // A fully funded escrow stays fully funded; a partly funded one keeps
+        // what it holds, up to the new total
+        let held = ctx.accounts.escrow.deposited + change_order.deposited;
+        let target = if ctx.accounts.escrow.deposited >= old_amount {
+            new_amount
+        } else {
+            held.min(new_amount)
+        };
 
-            // Transfer invoice amount to creator (paid by pool)
-            let transfer_to_creator = CpiContext::new_with_signer(
+        if target > held {
+            let transfer_ctx = CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 Transfer {
-                    from: ctx.accounts.pool_vault.to_account_info(),
-                    to: ctx.accounts.creator_token_account.to_account_info(),
-                    authority: pool.to_account_info(),
+                    from: ctx.accounts.client_token_account.to_account_info(),
+                    to: ctx.accounts.escrow_token_account.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
                 },
-                signer_seeds,
             );
-            token::transfer(transfer_to_creator, entry.invoice_amount)?;
-
-            // Deduct payout from pool
-            pool.total_balance = pool.total_balance.saturating_sub(entry.invoice_amount);
-
-            emit!(LotteryWon {
-                entry: entry.key(),
-                invoice: invoice.key(),
-                client: entry.client,
-                amount_won: entry.invoice_amount,
-            });
-        } else {
-            // LOSE: Pay invoice amount to creator
-            entry.status = LotteryStatus::Lost;
-
-            let seeds = &[
-                b"lottery_pool",
-                token_mint.as_ref(),
-                &[pool_bump],
-            ];
+            token::transfer(transfer_ctx, target - held)?;
+        } else if target < held {
+            // Refunds would have to be split across contributors
+            require!(invoice.contributed == 0, InvoiceError::CrowdfundedEscrow);
+            require!(invoice.yield_deposited == 0, InvoiceError::StrategyFundsDeployed);
+            let seeds = &[b"escrow", invoice_key.as_ref(), &[ctx.accounts.escrow.bump]];
             let signer_seeds = &[&seeds[..]];
 
             let transfer_ctx = CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 Transfer {
-                    from: ctx.accounts.pool_vault.to_account_info(),
-                    to: ctx.accounts.creator_token_account.to_account_info(),
-                    authority: pool.to_account_info(),
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    to: ctx.accounts.client_token_account.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
                 },
                 signer_seeds,
             );
-            token::transfer(transfer_ctx, entry.invoice_amount)?;
-
-            emit!(LotteryLost {
-                entry: entry.key(),
-                invoice: invoice.key(),
-                client: entry.client,
-            });
+            token::transfer(transfer_ctx, held - target)?;
         }
 
-        // Mark invoice as paid
-        invoice.status = InvoiceStatus::Paid;
-        invoice.paid_at = clock.unix_timestamp;
-        invoice.client = entry.client;
+        ctx.accounts.escrow.deposited = target;
+
+        let invoice = &mut ctx.accounts.invoice;
+        invoice.milestones = invoice.amended_milestones(&ctx.accounts.change_order.milestones);
+        invoice.amount = new_amount;
+        invoice.current_milestone = invoice
+            .milestones
+            .iter()
+            .position(|m| !m.completed)
+            .unwrap_or(invoice.milestones.len()) as u8;
+
+        emit!(ChangeOrderAccepted {
+            invoice_key,
+            accepted_by: authority,
+            old_amount,
+            new_amount,
+        });
 
         Ok(())
     }
 
-    /// Pause/unpause lottery pool (admin only)
-    pub fn toggle_lottery_pool(ctx: Context<ToggleLotteryPool>) -> Result<()> {
-        let pool = &mut ctx.accounts.lottery_pool;
-        pool.paused = !pool.paused;
+    /// Withdraw or decline a pending change order (either party), returning
+    /// any top-up the client deposited
+    pub fn cancel_change_order(ctx: Context<CancelChangeOrder>) -> Result<()> {
+        let invoice = &ctx.accounts.invoice;
+        let invoice_key = invoice.key();
+        let authority = ctx.accounts.authority.key();
 
-        emit!(LotteryPoolToggled {
-            pool: pool.key(),
-            paused: pool.paused,
+        require!(
+            authority == invoice.creator || authority == invoice.client,
+            InvoiceError::Unauthorized
+        );
+
+        let refund = ctx.accounts.change_order.deposited;
+        release_change_order_vault(
+            &ctx.accounts.token_program,
+            &ctx.accounts.change_order,
+            &ctx.accounts.change_order_vault,
+            ctx.accounts.client_token_account.to_account_info(),
+            ctx.accounts.proposer.to_account_info(),
+        )?;
+
+        emit!(ChangeOrderCancelled {
+            invoice_key,
+            cancelled_by: authority,
+            refunded: refund,
         });
 
         Ok(())
     }
-}
 
-// === ACCOUNTS ===
+    /// Move an escrow funded under the legacy `invoice_id`-only seeds into the
+    /// per-invoice namespace. Either party can migrate; legacy rent goes to the client.
+    pub fn migrate_escrow(ctx: Context<MigrateEscrow>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let escrow = &mut ctx.accounts.escrow;
 
-#[derive(Accounts)]
-#[instruction(invoice_id: String)]
-pub struct CreateInvoice<'info> {
-    #[account(
-        init,
-        payer = creator,
-        space = Invoice::space(&invoice_id),
-        seeds = [b"invoice", creator.key().as_ref(), invoice_id.as_bytes()],
-        bump
-    )]
-    pub invoice: Account<'info, Invoice>,
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            ctx.accounts.authority.key() == invoice.creator
+                || ctx.accounts.authority.key() == invoice.client,
+            InvoiceError::Unauthorized
+        );
 
-    #[account(mut)]
-    pub creator: Signer<'info>,
+        require!(invoice.escrow_funded, InvoiceError::EscrowNotFunded);
+        // Legacy seeds omit the creator, so invoices sharing an `invoice_id`
+        // share the PDA. Only one of them can have funded it, and only that
+        // one was marked when its v0 layout was migrated. The new escrow is
+        // `init`, so an invoice already holding one cannot get here either.
+        require!(invoice.legacy_escrow, InvoiceError::LegacyEscrowMismatch);
+
+        let legacy_escrow = ctx.accounts.legacy_escrow.to_account_info();
+        {
+            let data = legacy_escrow.try_borrow_data()?;
+            require!(
+                data.starts_with(&Escrow::DISCRIMINATOR),
+                InvoiceError::UnsupportedAccount
+            );
+            EscrowV0::deserialize(&mut &data[8..])?;
+        }
 
-    pub system_program: Program<'info, System>,
-}
+        // Released milestones were paid out of the legacy vault already
+        let released: u64 = invoice
+            .milestones
+            .iter()
+            .filter(|m| m.completed)
+            .map(|m| m.amount)
+            .sum();
+        let amount = ctx.accounts.legacy_escrow_token_account.amount;
+        require!(
+            amount >= invoice.amount.saturating_sub(released),
+            InvoiceError::LegacyEscrowMismatch
+        );
 
-#[derive(Accounts)]
-pub struct FundEscrow<'info> {
-    #[account(
-        mut,
-        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
-        bump = invoice.bump
-    )]
-    pub invoice: Account<'info, Invoice>,
+        escrow.invoice = invoice.key();
+        escrow.deposited = amount.checked_add(released).unwrap();
+        escrow.released = released;
+        escrow.bump = ctx.bumps.escrow;
+        invoice.legacy_escrow = false;
 
-    #[account(
-        init,
-        payer = client,
-        space = Escrow::SPACE,
-        seeds = [b"escrow", invoice.invoice_id.as_bytes()],
-        bump
-    )]
-    pub escrow: Account<'info, Escrow>,
+        let invoice_id = invoice.invoice_id.clone();
+        let seeds = &[
+            b"escrow",
+            invoice_id.as_bytes(),
+            &[ctx.bumps.legacy_escrow],
+        ];
+        let signer_seeds = &[&seeds[..]];
 
-    #[account(
-        init,
-        payer = client,
-        token::mint = token_mint,
-        token::authority = escrow,
-        seeds = [b"escrow_vault", invoice.invoice_id.as_bytes()],
-        bump
-    )]
-    pub escrow_token_account: Account<'info, TokenAccount>,
+        // Move the remaining balance into the new vault
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.legacy_escrow_token_account.to_account_info(),
+                to: ctx.accounts.escrow_token_account.to_account_info(),
+                authority: legacy_escrow.clone(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, amount)?;
+
+        // Close the legacy vault and escrow, returning rent to the client
+        let close_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.legacy_escrow_token_account.to_account_info(),
+                destination: ctx.accounts.client.to_account_info(),
+                authority: legacy_escrow.clone(),
+            },
+            signer_seeds,
+        );
+        token::close_account(close_ctx)?;
+
+        let client = ctx.accounts.client.to_account_info();
+        **client.try_borrow_mut_lamports()? += legacy_escrow.lamports();
+        **legacy_escrow.try_borrow_mut_lamports()? = 0;
+        legacy_escrow.assign(&system_program::ID);
+        legacy_escrow.realloc(0, false)?;
+
+        emit!(EscrowMigrated {
+            invoice_key: invoice.key(),
+            legacy_escrow: legacy_escrow.key(),
+            escrow: escrow.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Mark invoice as paid (for direct payments without escrow)
+    pub fn mark_paid(ctx: Context<MarkPaid>, tx_signature: String) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let clock = Clock::get()?;
+
+        require!(
+            matches!(invoice.status, InvoiceStatus::Pending | InvoiceStatus::Accepted),
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(invoice.is_payable(), InvoiceError::AcceptanceRequired);
+        require!(invoice.share_count == 0, InvoiceError::SplitInvoice);
+        require!(invoice.hold_period == 0, InvoiceError::HoldRequired);
+        require!(invoice.vesting_duration == 0, InvoiceError::VestingRequired);
+        require!(tx_signature.len() <= 88, InvoiceError::TxSignatureTooLong);
+
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Paid,
+            ctx.accounts.payer.key(),
+            clock.unix_timestamp,
+        );
+        invoice.paid_at = clock.unix_timestamp;
+        if invoice.client == Pubkey::default() {
+            invoice.client = ctx.accounts.payer.key();
+        }
+
+        emit!(InvoicePaid {
+            invoice_key: invoice.key(),
+            payer: ctx.accounts.payer.key(),
+            tx_signature,
+            paid_at: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Assign a fixed share of an unpaid invoice to a debtor (creator only)
+    pub fn add_debtor_share(
+        ctx: Context<AddDebtorShare>,
+        debtor: Pubkey,
+        amount: u64,
+    ) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+
+        require!(
+            invoice.status == InvoiceStatus::Pending,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(invoice.milestones.is_empty(), InvoiceError::SplitInvoice);
+        require!(invoice.hold_period == 0, InvoiceError::HoldRequired);
+        require!(invoice.vesting_duration == 0, InvoiceError::VestingRequired);
+        require!(amount > 0, InvoiceError::InvalidAmount);
+
+        let shares_total = invoice.shares_total.checked_add(amount).unwrap();
+        require!(shares_total <= invoice.amount, InvoiceError::SharesExceedAmount);
+        invoice.shares_total = shares_total;
+        invoice.share_count += 1;
+
+        let share = &mut ctx.accounts.share;
+        share.invoice = invoice.key();
+        share.debtor = debtor;
+        share.amount = amount;
+        share.paid = false;
+        share.paid_at = 0;
+        share.bump = ctx.bumps.share;
+
+        emit!(DebtorShareAdded {
+            invoice_key: invoice.key(),
+            debtor,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Pay a debtor's share straight to the creator; the invoice is paid once
+    /// every share is
+    pub fn pay_share(ctx: Context<PayShare>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let share = &mut ctx.accounts.share;
+        let clock = Clock::get()?;
+
+        require!(
+            matches!(invoice.status, InvoiceStatus::Pending | InvoiceStatus::Accepted),
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(invoice.is_payable(), InvoiceError::AcceptanceRequired);
+        require!(
+            invoice.shares_total == invoice.amount,
+            InvoiceError::SharesIncomplete
+        );
+        require!(!share.paid, InvoiceError::ShareAlreadyPaid);
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.debtor_token_account.to_account_info(),
+                to: ctx.accounts.creator_token_account.to_account_info(),
+                authority: ctx.accounts.debtor.to_account_info(),
+            },
+        );
+        token::transfer(transfer_ctx, share.amount)?;
+
+        share.paid = true;
+        share.paid_at = clock.unix_timestamp;
+        invoice.shares_paid += 1;
+
+        let invoice_key = invoice.key();
+        emit!(SharePaid {
+            invoice_key,
+            debtor: share.debtor,
+            amount: share.amount,
+            shares_paid: invoice.shares_paid,
+            share_count: invoice.share_count,
+        });
+
+        if invoice.shares_paid == invoice.share_count {
+            invoice.transition(
+                invoice_key,
+                InvoiceStatus::Paid,
+                share.debtor,
+                clock.unix_timestamp,
+            );
+            invoice.paid_at = clock.unix_timestamp;
+        }
+
+        Ok(())
+    }
+
+    /// Pay the full invoice into a hold vault the creator can withdraw from
+    /// once the hold period ends without a chargeback
+    pub fn pay_with_hold(ctx: Context<PayWithHold>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let hold = &mut ctx.accounts.hold;
+        let clock = Clock::get()?;
+
+        invoice.check_payable(ctx.accounts.client.key())?;
+        require!(invoice.hold_period > 0, InvoiceError::InvalidHoldPeriod);
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.client_token_account.to_account_info(),
+                to: ctx.accounts.hold_token_account.to_account_info(),
+                authority: ctx.accounts.client.to_account_info(),
+            },
+        );
+        token::transfer(transfer_ctx, invoice.amount)?;
+
+        let invoice_key = invoice.key();
+        hold.invoice = invoice_key;
+        hold.amount = invoice.amount;
+        hold.release_at = clock.unix_timestamp.checked_add(invoice.hold_period).unwrap();
+        hold.chargeback_reason = 0;
+        hold.chargeback_evidence = [0u8; 32];
+        hold.chargeback_requested_at = 0;
+        hold.bump = ctx.bumps.hold;
+
+        invoice.client = ctx.accounts.client.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Paid,
+            ctx.accounts.client.key(),
+            clock.unix_timestamp,
+        );
+        invoice.paid_at = clock.unix_timestamp;
+
+        emit!(PaymentHeld {
+            invoice_key,
+            client: invoice.client,
+            amount: hold.amount,
+            release_at: hold.release_at,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw a held payment once the hold period has passed (creator only)
+    pub fn withdraw_after_hold(ctx: Context<WithdrawAfterHold>) -> Result<()> {
+        let invoice = &ctx.accounts.invoice;
+        let hold = &ctx.accounts.hold;
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status != InvoiceStatus::Disputed,
+            InvoiceError::ChargebackPending
+        );
+        require!(
+            invoice.status == InvoiceStatus::Paid,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            clock.unix_timestamp >= hold.release_at,
+            InvoiceError::HoldPeriodActive
+        );
+
+        let amount = ctx.accounts.hold_token_account.amount;
+        release_hold(
+            &ctx.accounts.token_program,
+            hold,
+            &ctx.accounts.hold_token_account,
+            &[(ctx.accounts.creator_token_account.to_account_info(), amount)],
+            ctx.accounts.client.to_account_info(),
+        )?;
+
+        emit!(HoldWithdrawn {
+            invoice_key: invoice.key(),
+            creator: invoice.creator,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Contest a held payment before the hold period ends (client only)
+    pub fn request_chargeback(
+        ctx: Context<RequestChargeback>,
+        reason_code: u8,
+        evidence_hash: [u8; 32],
+    ) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let hold = &mut ctx.accounts.hold;
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::Paid,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            clock.unix_timestamp < hold.release_at,
+            InvoiceError::HoldPeriodEnded
+        );
+
+        hold.chargeback_reason = reason_code;
+        hold.chargeback_evidence = evidence_hash;
+        hold.chargeback_requested_at = clock.unix_timestamp;
+
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Disputed,
+            ctx.accounts.client.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(ChargebackRequested {
+            invoice_key,
+            client: ctx.accounts.client.key(),
+            reason_code,
+            evidence_hash,
+            requested_at: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Settle a chargeback. The creator may concede by refunding in full;
+    /// the arbiter may split the held payment, less their fee.
+    pub fn resolve_chargeback(ctx: Context<ResolveChargeback>, client_amount: u64) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let invoice_key = invoice.key();
+        let authority = ctx.accounts.authority.key();
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::Disputed,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            ctx.accounts.hold.chargeback_requested_at > 0,
+            InvoiceError::InvalidInvoiceStatus
+        );
+
+        let held = ctx.accounts.hold_token_account.amount;
+        let arbiter_fee = if authority == invoice.creator {
+            require!(client_amount == held, InvoiceError::InvalidDisputeSplit);
+            0
+        } else {
+            require!(
+                invoice.arbiter != Pubkey::default() && authority == invoice.arbiter,
+                InvoiceError::Unauthorized
+            );
+            held
+                .checked_mul(invoice.arbiter_fee_bps as u64)
+                .unwrap()
+                .checked_div(BPS_DIVISOR)
+                .unwrap()
+        };
+        let distributable = held.checked_sub(arbiter_fee).unwrap();
+        require!(
+            client_amount <= distributable,
+            InvoiceError::InvalidDisputeSplit
+        );
+        let creator_amount = distributable - client_amount;
+
+        release_hold(
+            &ctx.accounts.token_program,
+            &ctx.accounts.hold,
+            &ctx.accounts.hold_token_account,
+            &[
+                (ctx.accounts.arbiter_token_account.to_account_info(), arbiter_fee),
+                (ctx.accounts.creator_token_account.to_account_info(), creator_amount),
+                (ctx.accounts.client_token_account.to_account_info(), client_amount),
+            ],
+            ctx.accounts.client.to_account_info(),
+        )?;
+
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Resolved,
+            authority,
+            clock.unix_timestamp,
+        );
+
+        emit!(ChargebackResolved {
+            invoice_key,
+            resolved_by: authority,
+            creator_amount,
+            client_amount,
+            arbiter_fee,
+        });
+
+        Ok(())
+    }
+
+    /// Pay the full invoice into a vesting vault the creator claims from
+    /// over the invoice's vesting schedule
+    pub fn pay_with_vesting(ctx: Context<PayWithVesting>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let vesting = &mut ctx.accounts.vesting;
+        let clock = Clock::get()?;
+
+        invoice.check_payable(ctx.accounts.client.key())?;
+        require!(
+            invoice.vesting_duration > 0,
+            InvoiceError::InvalidVestingSchedule
+        );
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.client_token_account.to_account_info(),
+                to: ctx.accounts.vesting_token_account.to_account_info(),
+                authority: ctx.accounts.client.to_account_info(),
+            },
+        );
+        token::transfer(transfer_ctx, invoice.amount)?;
+
+        let invoice_key = invoice.key();
+        let now = clock.unix_timestamp;
+        vesting.invoice = invoice_key;
+        vesting.total = invoice.amount;
+        vesting.claimed = 0;
+        vesting.start_time = now;
+        vesting.cliff_time = now.checked_add(invoice.vesting_cliff).unwrap();
+        vesting.end_time = now.checked_add(invoice.vesting_duration).unwrap();
+        vesting.bump = ctx.bumps.vesting;
+
+        invoice.client = ctx.accounts.client.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Paid,
+            ctx.accounts.client.key(),
+            now,
+        );
+        invoice.paid_at = now;
+
+        emit!(PaymentVesting {
+            invoice_key,
+            client: invoice.client,
+            amount: vesting.total,
+            cliff_time: vesting.cliff_time,
+            end_time: vesting.end_time,
+        });
+
+        Ok(())
+    }
+
+    /// Claim everything vested so far (creator only). The vault is closed
+    /// once fully claimed.
+    pub fn claim_vested(ctx: Context<ClaimVested>) -> Result<()> {
+        let vesting = &mut ctx.accounts.vesting;
+        let invoice_key = vesting.invoice;
+        let clock = Clock::get()?;
+
+        let claimable = vesting
+            .vested_amount(clock.unix_timestamp)
+            .checked_sub(vesting.claimed)
+            .unwrap();
+        require!(claimable > 0, InvoiceError::NothingVested);
+
+        let seeds = &[b"vesting", invoice_key.as_ref(), &[vesting.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.vesting_token_account.to_account_info(),
+                to: ctx.accounts.creator_token_account.to_account_info(),
+                authority: vesting.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, claimable)?;
+
+        vesting.claimed = vesting.claimed.checked_add(claimable).unwrap();
+
+        emit!(VestedClaimed {
+            invoice_key,
+            creator: ctx.accounts.creator.key(),
+            amount: claimable,
+            total_claimed: vesting.claimed,
+        });
+
+        if vesting.claimed == vesting.total {
+            let close_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: ctx.accounts.vesting_token_account.to_account_info(),
+                    destination: ctx.accounts.client.to_account_info(),
+                    authority: vesting.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token::close_account(close_ctx)?;
+            vesting.close(ctx.accounts.client.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Cancel an unpaid invoice
+    pub fn cancel_invoice(ctx: Context<CancelInvoice>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let clock = Clock::get()?;
+
+        require!(
+            matches!(
+                invoice.status,
+                InvoiceStatus::Pending | InvoiceStatus::Accepted | InvoiceStatus::Rejected
+            ),
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            ctx.accounts.creator.key() == invoice.creator,
+            InvoiceError::Unauthorized
+        );
+        // Debtors who already paid their share would have nothing to reclaim
+        require!(invoice.shares_paid == 0, InvoiceError::SharesPartlyPaid);
+
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Cancelled,
+            ctx.accounts.creator.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(InvoiceCancelled {
+            invoice_key: invoice.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Cancel a funded escrow with both parties' signatures, refunding the
+    /// unreleased remainder to the client and closing the escrow. This is also
+    /// how a dispute on an invoice without an arbiter is settled.
+    pub fn cancel_funded_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelFundedEscrow<'info>>,
+    ) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let invoice_key = invoice.key();
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded
+                || (invoice.status == InvoiceStatus::Disputed
+                    && invoice.arbiter == Pubkey::default()),
+            InvoiceError::InvalidInvoiceStatus
+        );
+
+        if invoice.yield_deposited > 0 {
+            let strategy = StrategyAccounts::from_remaining(invoice, ctx.remaining_accounts)?;
+            recall_strategy(
+                invoice,
+                &mut ctx.accounts.escrow,
+                &mut ctx.accounts.escrow_token_account,
+                &ctx.accounts.token_program,
+                strategy,
+            )?;
+        }
+
+        let retainage = pay_retainage(
+            invoice,
+            &ctx.accounts.token_program,
+            &ctx.accounts.escrow,
+            &ctx.accounts.escrow_token_account,
+            &ctx.accounts.creator_token_account,
+        )?;
+        let refund = ctx.accounts.escrow_token_account.amount - retainage;
+        let client_refund = refund_escrow(
+            invoice,
+            &ctx.accounts.token_program,
+            &ctx.accounts.escrow,
+            &ctx.accounts.escrow_token_account,
+            &ctx.accounts.client_token_account,
+            ctx.accounts.client.to_account_info(),
+            refund,
+        )?;
+
+        if retainage > 0 {
+            emit!(RetainageReleased {
+                invoice_key,
+                amount: retainage,
+                released_by: ctx.accounts.creator.key(),
+            });
+        }
+
+        invoice.escrow_funded = false;
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Cancelled,
+            ctx.accounts.creator.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(EscrowRefunded {
+            invoice_key,
+            client: invoice.client,
+            amount: client_refund,
+            contributor_refunds: invoice.refund_pool,
+        });
+        emit!(InvoiceCancelled { invoice_key });
+
+        Ok(())
+    }
+
+    /// Pull back the unreleased escrow once the delivery deadline has passed
+    /// with no milestone awaiting review (client only)
+    pub fn reclaim_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReclaimEscrow<'info>>,
+    ) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let invoice_key = invoice.key();
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            invoice.delivery_deadline > 0 && clock.unix_timestamp >= invoice.delivery_deadline,
+            InvoiceError::DeliveryDeadlineNotReached
+        );
+        require!(
+            !invoice
+                .milestones
+                .iter()
+                .any(|m| !m.completed && m.submitted_at > 0),
+            InvoiceError::MilestonePendingReview
+        );
+
+        if invoice.yield_deposited > 0 {
+            let strategy = StrategyAccounts::from_remaining(invoice, ctx.remaining_accounts)?;
+            recall_strategy(
+                invoice,
+                &mut ctx.accounts.escrow,
+                &mut ctx.accounts.escrow_token_account,
+                &ctx.accounts.token_program,
+                strategy,
+            )?;
+        }
+
+        let retainage = pay_retainage(
+            invoice,
+            &ctx.accounts.token_program,
+            &ctx.accounts.escrow,
+            &ctx.accounts.escrow_token_account,
+            &ctx.accounts.creator_token_account,
+        )?;
+        let refund = ctx.accounts.escrow_token_account.amount - retainage;
+        let client_refund = refund_escrow(
+            invoice,
+            &ctx.accounts.token_program,
+            &ctx.accounts.escrow,
+            &ctx.accounts.escrow_token_account,
+            &ctx.accounts.client_token_account,
+            ctx.accounts.client.to_account_info(),
+            refund,
+        )?;
+
+        if retainage > 0 {
+            emit!(RetainageReleased {
+                invoice_key,
+                amount: retainage,
+                released_by: ctx.accounts.client.key(),
+            });
+        }
+
+        invoice.escrow_funded = false;
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Cancelled,
+            ctx.accounts.client.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(EscrowRefunded {
+            invoice_key,
+            client: invoice.client,
+            amount: client_refund,
+            contributor_refunds: invoice.refund_pool,
+        });
+        emit!(InvoiceCancelled { invoice_key });
+
+        Ok(())
+    }
+
+    /// Create the yield program whitelist, administered by the program's
+    /// upgrade authority
+    pub fn initialize_yield_config(ctx: Context<InitializeYieldConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.yield_config;
+        config.admin = ctx.accounts.admin.key();
+        config.programs = Vec::new();
+        config.bump = ctx.bumps.yield_config;
+        Ok(())
+    }
+
+    /// Allow invoices to deposit escrow into `yield_program` (admin only)
+    pub fn add_yield_program(ctx: Context<UpdateYieldConfig>, yield_program: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.yield_config;
+        if !config.programs.contains(&yield_program) {
+            require!(
+                config.programs.len() < MAX_YIELD_PROGRAMS,
+                InvoiceError::YieldConfigFull
+            );
+            config.programs.push(yield_program);
+        }
+
+        emit!(YieldWhitelistUpdated {
+            yield_program,
+            whitelisted: true,
+        });
+
+        Ok(())
+    }
+
+    /// Stop new invoices and deposits from using `yield_program` (admin only).
+    /// Funds already deployed can still be withdrawn.
+    pub fn remove_yield_program(
+        ctx: Context<UpdateYieldConfig>,
+        yield_program: Pubkey,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.yield_config;
+        let index = config
+            .programs
+            .iter()
+            .position(|p| *p == yield_program)
+            .ok_or(InvoiceError::YieldProgramNotWhitelisted)?;
+        config.programs.remove(index);
+
+        emit!(YieldWhitelistUpdated {
+            yield_program,
+            whitelisted: false,
+        });
+
+        Ok(())
+    }
+
+    /// Deposit unreleased escrow funds into the invoice's yield program (client only)
+    pub fn deposit_to_strategy(ctx: Context<DepositToStrategy>, amount: u64) -> Result<()> {
+        let invoice = &ctx.accounts.invoice;
+        let invoice_key = invoice.key();
+
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            invoice.yield_program != Pubkey::default(),
+            InvoiceError::NoYieldStrategy
+        );
+        // A program dropped from the whitelist takes no new deposits
+        require!(
+            ctx.accounts.yield_config.programs.contains(&invoice.yield_program),
+            InvoiceError::YieldProgramNotWhitelisted
+        );
+        require!(invoice.yield_deposited == 0, InvoiceError::StrategyFundsDeployed);
+        require!(
+            amount > 0 && amount <= invoice.unreleased_funding(&ctx.accounts.escrow),
+            InvoiceError::InvalidAmount
+        );
+
+        let ix = Instruction {
+            program_id: invoice.yield_program,
+            accounts: vec![
+                AccountMeta::new_readonly(ctx.accounts.yield_pool.key(), false),
+                AccountMeta::new(ctx.accounts.yield_vault.key(), false),
+                AccountMeta::new(ctx.accounts.yield_position.key(), false),
+                AccountMeta::new(ctx.accounts.escrow_token_account.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.escrow.key(), true),
+                AccountMeta::new(ctx.accounts.client.key(), true),
+                AccountMeta::new_readonly(ctx.accounts.token_program.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.system_program.key(), false),
+            ],
+            data: yield_instruction_data("deposit", &amount.to_le_bytes()),
+        };
+        let seeds = &[b"escrow", invoice_key.as_ref(), &[ctx.accounts.escrow.bump]];
+        invoke_signed(
+            &ix,
+            &[
+                ctx.accounts.yield_pool.to_account_info(),
+                ctx.accounts.yield_vault.to_account_info(),
+                ctx.accounts.yield_position.to_account_info(),
+                ctx.accounts.escrow_token_account.to_account_info(),
+                ctx.accounts.escrow.to_account_info(),
+                ctx.accounts.client.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                ctx.accounts.yield_program.to_account_info(),
+            ],
+            &[&seeds[..]],
+        )?;
+
+        let invoice = &mut ctx.accounts.invoice;
+        invoice.yield_deposited = amount;
+
+        emit!(StrategyDeposited {
+            invoice_key,
+            yield_program: invoice.yield_program,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw deployed funds back into escrow, paying out any yield per the
+    /// invoice's split (anyone). Payouts from escrow do this themselves when
+    /// passed the strategy accounts.
+    pub fn withdraw_from_strategy(ctx: Context<WithdrawFromStrategy>) -> Result<()> {
+        let invoice_key = ctx.accounts.invoice.key();
+        require!(
+            ctx.accounts.invoice.yield_deposited > 0,
+            InvoiceError::NoStrategyDeposit
+        );
+
+        let strategy = StrategyAccounts {
+            yield_program: ctx.accounts.yield_program.to_account_info(),
+            yield_pool: ctx.accounts.yield_pool.to_account_info(),
+            yield_vault: ctx.accounts.yield_vault.to_account_info(),
+            yield_position: ctx.accounts.yield_position.to_account_info(),
+            creator_token_account: ctx.accounts.creator_token_account.to_account_info(),
+            client_token_account: ctx.accounts.client_token_account.to_account_info(),
+            client: ctx.accounts.client.to_account_info(),
+        };
+        recall_strategy(
+            &mut ctx.accounts.invoice,
+            &mut ctx.accounts.escrow,
+            &mut ctx.accounts.escrow_token_account,
+            &ctx.accounts.token_program,
+            strategy,
+        )?;
+
+        if let Some(event) = ctx
+            .accounts
+            .invoice
+            .funding_required(invoice_key, &ctx.accounts.escrow)
+        {
+            emit!(event);
+        }
+
+        Ok(())
+    }
+
+    /// Create user profile
+    pub fn create_profile(
+        ctx: Context<CreateProfile>,
+        name: String,
+        email: String,
+        business_name: Option<String>,
+    ) -> Result<()> {
+        let profile = &mut ctx.accounts.profile;
+
+        require!(name.len() <= 64, InvoiceError::NameTooLong);
+        require!(email.len() <= 128, InvoiceError::EmailTooLong);
+
+        profile.wallet = ctx.accounts.owner.key();
+        profile.name = name;
+        profile.email = email;
+        profile.business_name = business_name.unwrap_or_default();
+        profile.total_invoices = 0;
+        profile.total_received = 0;
+        profile.bump = ctx.bumps.profile;
+        profile.version = UserProfile::VERSION;
+
+        Ok(())
+    }
+
+    /// Accept an invoice as its designated client, making it payable when acceptance is required
+    pub fn accept_invoice(ctx: Context<RespondToInvoice>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::Pending,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        // Letting anyone accept an open invoice would bind it to them as client
+        require!(
+            invoice.client != Pubkey::default() && invoice.client == ctx.accounts.client.key(),
+            InvoiceError::Unauthorized
+        );
+
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Accepted,
+            ctx.accounts.client.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(InvoiceAccepted {
+            invoice_key,
+            client: invoice.client,
+        });
+
+        Ok(())
+    }
+
+    /// Reject an invoice addressed to the signer with a reason code
+    pub fn reject_invoice(ctx: Context<RespondToInvoice>, reason_code: u8) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::Pending,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        // Only a designated client can reject, otherwise anyone could grief open invoices
+        require!(
+            invoice.client != Pubkey::default() && invoice.client == ctx.accounts.client.key(),
+            InvoiceError::Unauthorized
+        );
+
+        invoice.rejection_reason = reason_code;
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Rejected,
+            ctx.accounts.client.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(InvoiceRejected {
+            invoice_key,
+            client: invoice.client,
+            reason_code,
+        });
+
+        Ok(())
+    }
+
+    /// Dispute a funded escrow, freezing all milestone releases (either party)
+    pub fn raise_dispute(
+        ctx: Context<RaiseDispute>,
+        reason_code: u8,
+        evidence_hash: [u8; 32],
+    ) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let authority = ctx.accounts.authority.key();
+        let clock = Clock::get()?;
+
+        // Paid invoices can still be disputed over the retainage they hold
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded
+                || (invoice.status == InvoiceStatus::Paid && invoice.retainage_held > 0),
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            authority == invoice.creator || authority == invoice.client,
+            InvoiceError::Unauthorized
+        );
+
+        invoice.dispute_raised_by = authority;
+        invoice.dispute_reason = reason_code;
+        invoice.dispute_evidence = evidence_hash;
+        invoice.disputed_at = clock.unix_timestamp;
+
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Disputed,
+            authority,
+            clock.unix_timestamp,
+        );
+
+        emit!(DisputeRaised {
+            invoice_key,
+            raised_by: authority,
+            reason_code,
+            evidence_hash,
+            disputed_at: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Settle a disputed escrow (arbiter only). After the arbiter fee,
+    /// `creator_amount` of the remaining balance goes to the creator and the
+    /// rest back to the client; the escrow and its vault are closed.
+    pub fn resolve_dispute<'info>(
+        ctx: Context<'_, '_, '_, 'info, ResolveDispute<'info>>,
+        creator_amount: u64,
+    ) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let invoice_key = invoice.key();
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::Disputed,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(
+            invoice.arbiter != Pubkey::default() && invoice.arbiter == ctx.accounts.arbiter.key(),
+            InvoiceError::Unauthorized
+        );
+        if invoice.yield_deposited > 0 {
+            let strategy = StrategyAccounts::from_remaining(invoice, ctx.remaining_accounts)?;
+            recall_strategy(
+                invoice,
+                &mut ctx.accounts.escrow,
+                &mut ctx.accounts.escrow_token_account,
+                &ctx.accounts.token_program,
+                strategy,
+            )?;
+        }
+
+        let remaining = ctx.accounts.escrow_token_account.amount;
+        let arbiter_fee = remaining
+            .checked_mul(invoice.arbiter_fee_bps as u64)
+            .unwrap()
+            .checked_div(BPS_DIVISOR)
+            .unwrap();
+        let distributable = remaining.checked_sub(arbiter_fee).unwrap();
+        require!(
+            creator_amount <= distributable,
+            InvoiceError::InvalidDisputeSplit
+        );
+        let client_amount = distributable - creator_amount;
+
+        let seeds = &[b"escrow", invoice_key.as_ref(), &[ctx.accounts.escrow.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        for (to, amount) in [
+            (ctx.accounts.arbiter_token_account.to_account_info(), arbiter_fee),
+            (ctx.accounts.creator_token_account.to_account_info(), creator_amount),
+        ] {
+            if amount == 0 {
+                continue;
+            }
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    to,
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token::transfer(transfer_ctx, amount)?;
+        }
+
+        // The client's side of the split is refunded like any other refund
+        refund_escrow(
+            invoice,
+            &ctx.accounts.token_program,
+            &ctx.accounts.escrow,
+            &ctx.accounts.escrow_token_account,
+            &ctx.accounts.client_token_account,
+            ctx.accounts.client.to_account_info(),
+            client_amount,
+        )?;
+
+        // Held retainage was part of the disputed balance the arbiter split
+        invoice.retainage_held = 0;
+        invoice.escrow_funded = false;
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Resolved,
+            ctx.accounts.arbiter.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(DisputeResolved {
+            invoice_key,
+            arbiter: ctx.accounts.arbiter.key(),
+            creator_amount,
+            client_amount,
+            arbiter_fee,
+        });
+
+        Ok(())
+    }
+
+    // ============== LOTTERY INSTRUCTIONS ==============
+
+    /// Initialize a lottery pool for a specific token
+    pub fn initialize_lottery_pool(
+        ctx: Context<InitializeLotteryPool>,
+        house_edge_bps: u16,
+        min_pool_reserve_bps: u16,
+        max_win_pct_bps: u16,
+    ) -> Result<()> {
+        require!(house_edge_bps <= MAX_HOUSE_EDGE_BPS, InvoiceError::HouseEdgeTooHigh);
+        require!(min_pool_reserve_bps <= 5000, InvoiceError::ReserveTooHigh);
+        require!(max_win_pct_bps <= MAX_WIN_PCT_BPS, InvoiceError::MaxWinTooHigh);
+
+        let pool = &mut ctx.accounts.lottery_pool;
+        pool.authority = ctx.accounts.authority.key();
+        pool.token_mint = ctx.accounts.token_mint.key();
+        pool.total_balance = 0;
+        pool.total_premiums_collected = 0;
+        pool.total_payouts = 0;
+        pool.total_entries = 0;
+        pool.total_wins = 0;
+        pool.house_edge_bps = house_edge_bps;
+        pool.min_pool_reserve_bps = min_pool_reserve_bps;
+        pool.max_win_pct_bps = max_win_pct_bps;
+        pool.paused = false;
+        pool.bump = ctx.bumps.lottery_pool;
+        pool.version = LotteryPool::VERSION;
+
+        emit!(LotteryPoolCreated {
+            pool: pool.key(),
+            token_mint: pool.token_mint,
+            house_edge_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Seed the lottery pool with initial funds
+    pub fn seed_lottery_pool(ctx: Context<SeedLotteryPool>, amount: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.lottery_pool;
+
+        require!(!pool.paused, InvoiceError::PoolPaused);
+        require!(amount > 0, InvoiceError::InvalidAmount);
+
+        // Transfer tokens from seeder to pool vault
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.seeder_token_account.to_account_info(),
+                to: ctx.accounts.pool_vault.to_account_info(),
+                authority: ctx.accounts.seeder.to_account_info(),
+            },
+        );
+        token::transfer(transfer_ctx, amount)?;
+
+        pool.total_balance = pool.total_balance.checked_add(amount).unwrap();
+
+        emit!(LotteryPoolSeeded {
+            pool: pool.key(),
+            amount,
+            new_balance: pool.total_balance,
+        });
+
+        Ok(())
+    }
+
+    /// Pay invoice with lottery option (premium for chance to win)
+    pub fn pay_with_lottery(
+        ctx: Context<PayWithLottery>,
+        premium_amount: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.lottery_pool;
+        let invoice = &ctx.accounts.invoice;
+        let entry = &mut ctx.accounts.lottery_entry;
+        let clock = Clock::get()?;
+
+        // Validations
+        require!(!pool.paused, InvoiceError::PoolPaused);
+        invoice.check_payable(ctx.accounts.client.key())?;
+        require!(invoice.hold_period == 0, InvoiceError::HoldRequired);
+        require!(invoice.vesting_duration == 0, InvoiceError::VestingRequired);
+        require!(premium_amount > 0, InvoiceError::InvalidAmount);
+
+        // Invoice must be at least 5 minutes old (prevent gaming)
+        require!(
+            clock.unix_timestamp - invoice.created_at >= 300,
+            InvoiceError::InvoiceTooNew
+        );
+
+        let invoice_amount = invoice.amount;
+
+        // Calculate max win based on pool balance, never dipping into the
+        // protocol's minimum reserve whatever the pool was configured with
+        let reserve_bps = pool.min_pool_reserve_bps.max(MIN_POOL_RESERVE_BPS);
+        let available_pool = pool.total_balance
+            .saturating_mul(BPS_DIVISOR - reserve_bps as u64)
+            / BPS_DIVISOR;
+        let max_win = available_pool
+            .saturating_mul(pool.max_win_pct_bps as u64)
+            / BPS_DIVISOR;
+
+        require!(invoice_amount <= max_win, InvoiceError::InvoiceExceedsMaxWin);
+
+        // Calculate win probability
+        // Formula: win_prob = premium / (invoice_amount * (1 + house_edge))
+        let house_edge_multiplier = BPS_DIVISOR + pool.house_edge_bps as u64;
+        let effective_invoice = invoice_amount
+            .checked_mul(house_edge_multiplier)
+            .unwrap()
+            / BPS_DIVISOR;
+
+        let win_probability_bps = (premium_amount as u128)
+            .checked_mul(BPS_DIVISOR as u128)
+            .unwrap()
+            .checked_div(effective_invoice as u128)
+            .unwrap_or(0) as u16;
+
+        // Cap probability at 95%
+        let win_probability_bps = win_probability_bps.min(9500);
+
+        // Transfer total payment (invoice + premium) from client to pool vault
+        let total_payment = invoice_amount.checked_add(premium_amount).unwrap();
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.client_token_account.to_account_info(),
+                to: ctx.accounts.pool_vault.to_account_info(),
+                authority: ctx.accounts.client.to_account_info(),
+            },
+        );
+        token::transfer(transfer_ctx, total_payment)?;
+
+        // Update pool balance (add premium only, invoice amount held for settlement)
+        pool.total_balance = pool.total_balance.checked_add(premium_amount).unwrap();
+        pool.total_premiums_collected = pool.total_premiums_collected.checked_add(premium_amount).unwrap();
+        pool.total_entries = pool.total_entries.checked_add(1).unwrap();
+
+        // Create lottery entry
+        entry.invoice = invoice.key();
+        entry.client = ctx.accounts.client.key();
+        entry.invoice_amount = invoice_amount;
+        entry.premium_paid = premium_amount;
+        entry.win_probability_bps = win_probability_bps;
+        entry.status = LotteryStatus::PendingVrf;
+        entry.random_result = None;
+        entry.created_at = clock.unix_timestamp;
+        entry.resolved_at = 0;
+        entry.bump = ctx.bumps.lottery_entry;
+        entry.version = LotteryEntry::VERSION;
+
+        emit!(LotteryEntryCreated {
+            entry: entry.key(),
+            invoice: invoice.key(),
+            client: ctx.accounts.client.key(),
+            invoice_amount,
+            premium_paid: premium_amount,
+            win_probability_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Settle lottery result (called with randomness - simplified without VRF for hackathon)
+    pub fn settle_lottery(
+        ctx: Context<SettleLottery>,
+        random_bytes: [u8; 32],
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.lottery_pool;
+        let invoice = &mut ctx.accounts.invoice;
+        let entry = &mut ctx.accounts.lottery_entry;
+        let clock = Clock::get()?;
+
+        require!(entry.status == LotteryStatus::PendingVrf, InvoiceError::LotteryAlreadySettled);
+
+        // Derive randomness (0-9999)
+        let random_value = u16::from_le_bytes([random_bytes[0], random_bytes[1]]) % 10000;
+
+        // Determine win/loss
+        let won = random_value < entry.win_probability_bps;
+
+        entry.random_result = Some(random_bytes);
+        entry.resolved_at = clock.unix_timestamp;
+
+        let token_mint = pool.token_mint;
+        let pool_bump = pool.bump;
+
+        if won {
+            // WIN: Refund invoice amount from pool to client
+            entry.status = LotteryStatus::Won;
+            pool.total_wins = pool.total_wins.checked_add(1).unwrap();
+            pool.total_payouts = pool.total_payouts.checked_add(entry.invoice_amount).unwrap();
+
+            // Transfer invoice amount back to client (they won!)
+            let seeds = &[
+                b"lottery_pool",
+                token_mint.as_ref(),
+                &[pool_bump],
+            ];
+            let signer_seeds = &[&seeds[..]];
+
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    to: ctx.accounts.client_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token::transfer(transfer_ctx, entry.invoice_amount)?;
+
+            // Transfer invoice amount to creator (paid by pool)
+            let transfer_to_creator = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    to: ctx.accounts.creator_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token::transfer(transfer_to_creator, entry.invoice_amount)?;
+
+            // Deduct payout from pool
+            pool.total_balance = pool.total_balance.saturating_sub(entry.invoice_amount);
+
+            emit!(LotteryWon {
+                entry: entry.key(),
+                invoice: invoice.key(),
+                client: entry.client,
+                amount_won: entry.invoice_amount,
+            });
+        } else {
+            // LOSE: Pay invoice amount to creator
+            entry.status = LotteryStatus::Lost;
+
+            let seeds = &[
+                b"lottery_pool",
+                token_mint.as_ref(),
+                &[pool_bump],
+            ];
+            let signer_seeds = &[&seeds[..]];
+
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    to: ctx.accounts.creator_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token::transfer(transfer_ctx, entry.invoice_amount)?;
+
+            emit!(LotteryLost {
+                entry: entry.key(),
+                invoice: invoice.key(),
+                client: entry.client,
+            });
+        }
+
+        // Mark invoice as paid
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Paid,
+            ctx.accounts.settler.key(),
+            clock.unix_timestamp,
+        );
+        invoice.paid_at = clock.unix_timestamp;
+        invoice.client = entry.client;
+
+        Ok(())
+    }
+
+    /// Pause/unpause lottery pool (admin only)
+    pub fn toggle_lottery_pool(ctx: Context<ToggleLotteryPool>) -> Result<()> {
+        let pool = &mut ctx.accounts.lottery_pool;
+        pool.paused = !pool.paused;
+
+        emit!(LotteryPoolToggled {
+            pool: pool.key(),
+            paused: pool.paused,
+        });
+
+        Ok(())
+    }
+
+    // ============== AUTOPAY INSTRUCTIONS ==============
+
+    /// Authorize automatic payment of a creator's invoices from the client's token account
+    pub fn create_autopay_mandate(
+        ctx: Context<CreateAutopayMandate>,
+        max_per_invoice: u64,
+        max_per_period: u64,
+        period_seconds: i64,
+        expires_at: i64,
+        allowance: u64,
+    ) -> Result<()> {
+        let mandate = &mut ctx.accounts.mandate;
+        let clock = Clock::get()?;
+
+        require!(max_per_invoice > 0, InvoiceError::InvalidAmount);
+        require!(max_per_period >= max_per_invoice, InvoiceError::InvalidAmount);
+        require!(period_seconds > 0, InvoiceError::InvalidMandatePeriod);
+        require!(expires_at > clock.unix_timestamp, InvoiceError::MandateExpired);
+
+        // Approve this mandate's delegate PDA to pull from the client's token account.
+        // SPL tokens allow one delegate per account, so this replaces any previous
+        // mandate's approval on the same account.
+        let approve_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Approve {
+                to: ctx.accounts.client_token_account.to_account_info(),
+                delegate: ctx.accounts.autopay_delegate.to_account_info(),
+                authority: ctx.accounts.client.to_account_info(),
+            },
+        );
+        token::approve(approve_ctx, allowance)?;
+
+        mandate.client = ctx.accounts.client.key();
+        mandate.creator = ctx.accounts.creator.key();
+        mandate.token_mint = ctx.accounts.token_mint.key();
+        mandate.max_per_invoice = max_per_invoice;
+        mandate.max_per_period = max_per_period;
+        mandate.period_seconds = period_seconds;
+        mandate.period_start = clock.unix_timestamp;
+        mandate.period_spent = 0;
+        mandate.expires_at = expires_at;
+        mandate.total_paid = 0;
+        mandate.bump = ctx.bumps.mandate;
+
+        emit!(AutopayMandateCreated {
+            mandate: mandate.key(),
+            client: mandate.client,
+            creator: mandate.creator,
+            token_mint: mandate.token_mint,
+            max_per_invoice,
+            max_per_period,
+            expires_at,
+        });
+
+        Ok(())
+    }
+
+    /// Close an autopay mandate (client only), revoking its delegate approval
+    pub fn cancel_autopay_mandate(ctx: Context<CancelAutopayMandate>) -> Result<()> {
+        // Leave the approval alone if another mandate has since replaced it
+        let delegate = ctx.accounts.autopay_delegate.key();
+        if ctx.accounts.client_token_account.delegate == COption::Some(delegate) {
+            let revoke_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Revoke {
+                    source: ctx.accounts.client_token_account.to_account_info(),
+                    authority: ctx.accounts.client.to_account_info(),
+                },
+            );
+            token::revoke(revoke_ctx)?;
+        }
+
+        emit!(AutopayMandateCancelled {
+            mandate: ctx.accounts.mandate.key(),
+            client: ctx.accounts.client.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Pay a due invoice under an autopay mandate (permissionless crank)
+    pub fn execute_autopay(ctx: Context<ExecuteAutopay>) -> Result<()> {
+        let mandate = &mut ctx.accounts.mandate;
+        let invoice = &mut ctx.accounts.invoice;
+        let clock = Clock::get()?;
+
+        invoice.check_payable(mandate.client)?;
+        require!(invoice.hold_period == 0, InvoiceError::HoldRequired);
+        require!(invoice.vesting_duration == 0, InvoiceError::VestingRequired);
+        require!(
+            invoice.milestones.is_empty(),
+            InvoiceError::MilestoneInvoiceNotAutopayable
+        );
+        require!(
+            clock.unix_timestamp >= invoice.due_date,
+            InvoiceError::InvoiceNotDue
+        );
+        require!(
+            clock.unix_timestamp < mandate.expires_at,
+            InvoiceError::MandateExpired
+        );
+        require!(
+            invoice.amount <= mandate.max_per_invoice,
+            InvoiceError::MandateLimitExceeded
+        );
+
+        // Start a new spending period once the current one has elapsed
+        if clock.unix_timestamp >= mandate.period_start.saturating_add(mandate.period_seconds) {
+            mandate.period_start = clock.unix_timestamp;
+            mandate.period_spent = 0;
+        }
+
+        let period_spent = mandate
+            .period_spent
+            .checked_add(invoice.amount)
+            .ok_or(InvoiceError::MandateLimitExceeded)?;
+        require!(
+            period_spent <= mandate.max_per_period,
+            InvoiceError::MandateLimitExceeded
+        );
+
+        // Transfer from client to creator as the approved delegate
+        let client_key = mandate.client;
+        let creator_key = mandate.creator;
+        let token_mint = mandate.token_mint;
+        let seeds = &[
+            b"autopay_delegate",
+            client_key.as_ref(),
+            creator_key.as_ref(),
+            token_mint.as_ref(),
+            &[ctx.bumps.autopay_delegate],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.client_token_account.to_account_info(),
+                to: ctx.accounts.creator_token_account.to_account_info(),
+                authority: ctx.accounts.autopay_delegate.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, invoice.amount)?;
+
+        mandate.period_spent = period_spent;
+        mandate.total_paid = mandate.total_paid.checked_add(invoice.amount).unwrap();
+
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Paid,
+            ctx.accounts.cranker.key(),
+            clock.unix_timestamp,
+        );
+        invoice.paid_at = clock.unix_timestamp;
+        invoice.client = client_key;
+
+        emit!(AutopayExecuted {
+            mandate: mandate.key(),
+            invoice_key,
+            client: client_key,
+            amount: invoice.amount,
+            paid_at: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // ============== STREAMING INSTRUCTIONS ==============
+
+    /// Deposit the invoice amount into a stream that vests linearly to the creator
+    pub fn fund_stream(ctx: Context<FundStream>, start_time: i64, end_time: i64) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let stream = &mut ctx.accounts.stream;
+        let clock = Clock::get()?;
+
+        invoice.check_payable(ctx.accounts.client.key())?;
+        require!(invoice.hold_period == 0, InvoiceError::HoldRequired);
+        require!(invoice.vesting_duration == 0, InvoiceError::VestingRequired);
+        require!(
+            invoice.milestones.is_empty(),
+            InvoiceError::MilestoneInvoiceNotStreamable
+        );
+        require!(
+            start_time < end_time && end_time > clock.unix_timestamp,
+            InvoiceError::InvalidStreamSchedule
+        );
+        // The stream must be fully vested by the invoice due date
+        require!(end_time <= invoice.due_date, InvoiceError::InvalidStreamSchedule);
+
+        // Transfer tokens from client to stream vault
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.client_token_account.to_account_info(),
+                to: ctx.accounts.stream_token_account.to_account_info(),
+                authority: ctx.accounts.client.to_account_info(),
+            },
+        );
+        token::transfer(transfer_ctx, invoice.amount)?;
+
+        stream.invoice = invoice.key();
+        stream.client = ctx.accounts.client.key();
+        stream.creator = invoice.creator;
+        stream.deposited = invoice.amount;
+        stream.withdrawn = 0;
+        stream.start_time = start_time;
+        stream.end_time = end_time;
+        stream.bump = ctx.bumps.stream;
+
+        invoice.client = ctx.accounts.client.key();
+        let invoice_key = invoice.key();
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Streaming,
+            ctx.accounts.client.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(StreamFunded {
+            invoice_key,
+            client: stream.client,
+            amount: stream.deposited,
+            start_time,
+            end_time,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw the vested portion of a stream (creator only)
+    pub fn withdraw_streamed(ctx: Context<WithdrawStreamed>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let stream = &mut ctx.accounts.stream;
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::Streaming,
+            InvoiceError::InvalidInvoiceStatus
+        );
+
+        let amount = stream
+            .vested_amount(clock.unix_timestamp)
+            .saturating_sub(stream.withdrawn);
+        require!(amount > 0, InvoiceError::NothingToWithdraw);
+
+        let invoice_key = invoice.key();
+        let seeds = &[b"stream", invoice_key.as_ref(), &[stream.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.stream_token_account.to_account_info(),
+                to: ctx.accounts.creator_token_account.to_account_info(),
+                authority: stream.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, amount)?;
+
+        stream.withdrawn = stream.withdrawn.checked_add(amount).unwrap();
+
+        // Fully withdrawn streams settle the invoice
+        if stream.withdrawn >= stream.deposited {
+            invoice.transition(
+                invoice_key,
+                InvoiceStatus::Paid,
+                ctx.accounts.creator.key(),
+                clock.unix_timestamp,
+            );
+            invoice.paid_at = clock.unix_timestamp;
+        }
+
+        emit!(StreamWithdrawn {
+            invoice_key,
+            amount,
+            total_withdrawn: stream.withdrawn,
+        });
+
+        Ok(())
+    }
+
+    /// Cancel a stream, paying out the vested portion and refunding the rest (client only)
+    pub fn cancel_stream(ctx: Context<CancelStream>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let stream = &mut ctx.accounts.stream;
+        let clock = Clock::get()?;
+
+        require!(
+            invoice.status == InvoiceStatus::Streaming,
+            InvoiceError::InvalidInvoiceStatus
+        );
+
+        let vested = stream.vested_amount(clock.unix_timestamp);
+        let creator_amount = vested.saturating_sub(stream.withdrawn);
+        let refund_amount = stream.deposited.saturating_sub(vested);
+
+        let invoice_key = invoice.key();
+        let seeds = &[b"stream", invoice_key.as_ref(), &[stream.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        if creator_amount > 0 {
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.stream_token_account.to_account_info(),
+                    to: ctx.accounts.creator_token_account.to_account_info(),
+                    authority: stream.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token::transfer(transfer_ctx, creator_amount)?;
+        }
+
+        if refund_amount > 0 {
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.stream_token_account.to_account_info(),
+                    to: ctx.accounts.client_token_account.to_account_info(),
+                    authority: stream.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token::transfer(transfer_ctx, refund_amount)?;
+        }
+
+        stream.withdrawn = vested;
+        stream.end_time = clock.unix_timestamp.clamp(stream.start_time, stream.end_time);
+        invoice.transition(
+            invoice_key,
+            InvoiceStatus::Cancelled,
+            ctx.accounts.client.key(),
+            clock.unix_timestamp,
+        );
+
+        emit!(StreamCancelled {
+            invoice_key,
+            paid_to_creator: creator_amount,
+            refunded: refund_amount,
+        });
+
+        Ok(())
+    }
+
+    // ============== MIGRATION INSTRUCTIONS ==============
+
+    /// Upgrade an account written with an older layout to the current version
+    pub fn migrate_account(ctx: Context<MigrateAccount>) -> Result<()> {
+        let account = ctx.accounts.account.to_account_info();
+
+        let (upgraded, from_version, to_version) = {
+            let data = account.try_borrow_data()?;
+            upgrade_account_data(account.key, &data)?
+        };
+
+        // Grow the account if the new layout no longer fits, topping up rent from the payer
+        if upgraded.len() > account.data_len() {
+            let rent = Rent::get()?.minimum_balance(upgraded.len());
+            let top_up = rent.saturating_sub(account.lamports());
+            if top_up > 0 {
+                let transfer_ctx = CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: account.clone(),
+                    },
+                );
+                system_program::transfer(transfer_ctx, top_up)?;
+            }
+            account.realloc(upgraded.len(), true)?;
+        }
+
+        account.try_borrow_mut_data()?[..upgraded.len()].copy_from_slice(&upgraded);
+
+        emit!(AccountMigrated {
+            account: account.key(),
+            from_version,
+            to_version,
+        });
+
+        Ok(())
+    }
+}
+
+// === ACCOUNTS ===
+
+#[derive(Accounts)]
+#[instruction(
+    invoice_id: String,
+    amount: u64,
+    token_mint: Pubkey,
+    due_date: i64,
+    memo: String,
+    milestones: Vec<Milestone>
+)]
+pub struct CreateInvoice<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = Invoice::space(&invoice_id, &memo, &milestones),
+        seeds = [b"invoice", creator.key().as_ref(), invoice_id.as_bytes()],
+        bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    /// Only needed when the invoice names a yield program
+    #[account(seeds = [b"yield_config"], bump = yield_config.bump)]
+    pub yield_config: Option<Account<'info, YieldConfig>>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(memo: String)]
+pub struct UpdateMemo<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        has_one = creator @ InvoiceError::Unauthorized,
+        realloc = Invoice::space(&invoice.invoice_id, &memo, &invoice.milestones),
+        realloc::payer = creator,
+        realloc::zero = false
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(milestones: Vec<Milestone>)]
+pub struct AddMilestones<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        has_one = creator @ InvoiceError::Unauthorized,
+        realloc = invoice.current_space() + milestones.iter().map(Milestone::space).sum::<usize>(),
+        realloc::payer = creator,
+        realloc::zero = false
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        init,
+        payer = client,
+        space = Escrow::SPACE,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = client,
+        token::mint = token_mint,
+        token::authority = escrow,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key(),
+        constraint = client_token_account.mint == token_mint.key() @ InvoiceError::InvalidMint
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(address = invoice.token_mint @ InvoiceError::InvalidMint)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TopUpEscrow<'info> {
+    #[account(
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key(),
+        constraint = client_token_account.mint == invoice.token_mint @ InvoiceError::InvalidMint
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ContributeEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = contributor,
+        space = Contribution::SPACE,
+        seeds = [b"contribution", invoice.key().as_ref(), contributor.key().as_ref()],
+        bump
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    #[account(
+        mut,
+        constraint = contributor_token_account.owner == contributor.key(),
+        constraint = contributor_token_account.mint == invoice.token_mint @ InvoiceError::InvalidMint
+    )]
+    pub contributor_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TopUpContribution<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"contribution", invoice.key().as_ref(), contributor.key().as_ref()],
+        bump = contribution.bump
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    #[account(
+        mut,
+        constraint = contributor_token_account.owner == contributor.key(),
+        constraint = contributor_token_account.mint == invoice.token_mint @ InvoiceError::InvalidMint
+    )]
+    pub contributor_token_account: Account<'info, TokenAccount>,
+
+    pub contributor: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseContribution<'info> {
+    #[account(
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"contribution", invoice.key().as_ref(), contributor.key().as_ref()],
+        bump = contribution.bump,
+        close = contributor
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+}
+
+/// Move a contributor's deposit into the escrow, capped at what is still
+/// outstanding, and record it against their `Contribution`
+#[allow(clippy::too_many_arguments)]
+fn deposit_contribution<'info>(
+    invoice: &mut Account<'info, Invoice>,
+    escrow: &mut Account<'info, Escrow>,
+    escrow_token_account: &Account<'info, TokenAccount>,
+    contribution: &mut Account<'info, Contribution>,
+    contributor_token_account: &Account<'info, TokenAccount>,
+    contributor: &Signer<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let invoice_key = invoice.key();
+
+    require!(
+        invoice.status == InvoiceStatus::EscrowFunded,
+        InvoiceError::InvalidInvoiceStatus
+    );
+    require!(amount > 0, InvoiceError::InvalidAmount);
+
+    let outstanding = invoice.amount.saturating_sub(escrow.deposited);
+    require!(outstanding > 0, InvoiceError::EscrowFullyFunded);
+    let deposit = amount.min(outstanding);
+
+    let transfer_ctx = CpiContext::new(
+        token_program.to_account_info(),
+        Transfer {
+            from: contributor_token_account.to_account_info(),
+            to: escrow_token_account.to_account_info(),
+            authority: contributor.to_account_info(),
+        },
+    );
+    token::transfer(transfer_ctx, deposit)?;
+
+    contribution.amount = contribution.amount.checked_add(deposit).unwrap();
+    invoice.contributed = invoice.contributed.checked_add(deposit).unwrap();
+    escrow.deposited = escrow.deposited.checked_add(deposit).unwrap();
+
+    emit!(EscrowContributed {
+        invoice_key,
+        contributor: contribution.contributor,
+        amount: deposit,
+        total_contributed: contribution.amount,
+    });
+    if let Some(event) = invoice.funding_required(invoice_key, escrow) {
+        emit!(event);
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct ClaimRefund<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"contribution", invoice.key().as_ref(), contributor.key().as_ref()],
+        bump = contribution.bump,
+        close = contributor
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    #[account(
+        mut,
+        constraint = contributor_token_account.owner == contributor.key()
+    )]
+    pub contributor_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+
+    /// CHECK: Invoice client receiving the escrow accounts' rent
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseMilestone<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    /// Client for `release_milestone`; anyone for `auto_release_milestone` and
+    /// post-warranty `release_retainage`
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> ReleaseMilestone<'info> {
+    fn check_releasable(&self, milestone_idx: usize) -> Result<()> {
+        let invoice = &self.invoice;
+
+        require!(
+            invoice.status != InvoiceStatus::Disputed,
+            InvoiceError::InvoiceDisputed
+        );
+        require!(
+            invoice.status == InvoiceStatus::EscrowFunded,
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(invoice.escrow_funded, InvoiceError::EscrowNotFunded);
+        require!(
+            milestone_idx < invoice.milestones.len(),
+            InvoiceError::InvalidMilestoneIndex
+        );
+        require!(
+            !invoice.milestones[milestone_idx].completed,
+            InvoiceError::MilestoneAlreadyCompleted
+        );
+
+        Ok(())
+    }
+
+    /// Transfer `amount` of a milestone from escrow to the creator, marking
+    /// it complete once fully released
+    fn pay_milestone(&mut self, milestone_idx: usize, amount: u64, now: i64) -> Result<()> {
+        let invoice_key = self.invoice.key();
+        require!(
+            amount <= self.invoice.unreleased_funding(&self.escrow),
+            InvoiceError::InsufficientEscrowFunding
+        );
+
+        // Retainage stays in the vault until final acceptance or warranty expiry
+        let retained = amount
+            .checked_mul(self.invoice.retainage_bps as u64)
+            .unwrap()
+            .checked_div(BPS_DIVISOR)
+            .unwrap();
+        self.pay_creator(amount - retained)?;
+
+        let invoice = &mut self.invoice;
+        invoice.retainage_held = invoice.retainage_held.checked_add(retained).unwrap();
+        let milestone = &mut invoice.milestones[milestone_idx];
+        milestone.amount_released = milestone.amount_released.checked_add(amount).unwrap();
+        if milestone.unreleased() > 0 {
+            return Ok(());
+        }
+        milestone.completed = true;
+        milestone.completed_at = now;
+
+        // Point at the first milestone still outstanding
+        while (invoice.current_milestone as usize) < invoice.milestones.len()
+            && invoice.milestones[invoice.current_milestone as usize].completed
+        {
+            invoice.current_milestone += 1;
+        }
+
+        // Check if all milestones complete
+        if invoice.milestones.iter().all(|m| m.completed) {
+            invoice.transition(invoice_key, InvoiceStatus::Paid, self.authority.key(), now);
+            invoice.paid_at = now;
+        } else if let Some(event) = invoice.funding_required(invoice_key, &self.escrow) {
+            emit!(event);
+        }
+
+        Ok(())
+    }
+
+    /// Pull funds still in the yield strategy back into escrow, from the
+    /// strategy accounts passed as remaining accounts
+    fn recall_deployed(&mut self, remaining: &[AccountInfo<'info>]) -> Result<()> {
+        if self.invoice.yield_deposited == 0 {
+            return Ok(());
+        }
+        let strategy = StrategyAccounts::from_remaining(&self.invoice, remaining)?;
+        recall_strategy(
+            &mut self.invoice,
+            &mut self.escrow,
+            &mut self.escrow_token_account,
+            &self.token_program,
+            strategy,
+        )
+    }
+
+    /// Transfer `amount` from escrow to the creator
+    fn pay_creator(&mut self, amount: u64) -> Result<()> {
+        let invoice_key = self.invoice.key();
+        let seeds = &[b"escrow", invoice_key.as_ref(), &[self.escrow.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.escrow_token_account.to_account_info(),
+                to: self.creator_token_account.to_account_info(),
+                authority: self.escrow.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, amount)?;
+
+        self.escrow.released = self.escrow.released.checked_add(amount).unwrap();
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(milestone_index: u8, deliverable_hash: Option<[u8; 32]>, deliverable_uri: String)]
+pub struct SubmitMilestone<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        has_one = creator @ InvoiceError::Unauthorized,
+        realloc = invoice.space_with_deliverable_uri(milestone_index as usize, &deliverable_uri),
+        realloc::payer = creator,
+        realloc::zero = false
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMilestone<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(milestones: Vec<Milestone>)]
+pub struct ProposeChangeOrder<'info> {
+    #[account(
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = ChangeOrder::space(&milestones),
+        seeds = [b"change_order", invoice.key().as_ref()],
+        bump
+    )]
+    pub change_order: Account<'info, ChangeOrder>,
+
+    /// Holds the client's top-up apart from the escrow until the order settles
+    #[account(
+        init,
+        payer = proposer,
+        token::mint = token_mint,
+        token::authority = change_order,
+        seeds = [b"change_order_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub change_order_vault: Account<'info, TokenAccount>,
+
+    #[account(address = invoice.token_mint @ InvoiceError::InvalidMint)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == invoice.client
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptChangeOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        realloc = Invoice::space(
+            &invoice.invoice_id,
+            &invoice.memo,
+            &invoice.amended_milestones(&change_order.milestones)
+        ),
+        realloc::payer = authority,
+        realloc::zero = false
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"change_order", invoice.key().as_ref()],
+        bump = change_order.bump,
+        close = proposer
+    )]
+    pub change_order: Account<'info, ChangeOrder>,
+
+    #[account(
+        mut,
+        seeds = [b"change_order_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub change_order_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == invoice.client
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Change order proposer receiving its rent
+    #[account(mut, address = change_order.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelChangeOrder<'info> {
+    #[account(
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"change_order", invoice.key().as_ref()],
+        bump = change_order.bump,
+        close = proposer
+    )]
+    pub change_order: Account<'info, ChangeOrder>,
+
+    #[account(
+        mut,
+        seeds = [b"change_order_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub change_order_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == invoice.client
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Change order proposer receiving its rent
+    #[account(mut, address = change_order.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+/// Move a change order's top-up to `to`, then close its vault, sending the
+/// rent to the proposer who paid it
+fn release_change_order_vault<'info>(
+    token_program: &Program<'info, Token>,
+    change_order: &Account<'info, ChangeOrder>,
+    change_order_vault: &Account<'info, TokenAccount>,
+    to: AccountInfo<'info>,
+    proposer: AccountInfo<'info>,
+) -> Result<()> {
+    let invoice_key = change_order.invoice;
+    let seeds = &[b"change_order", invoice_key.as_ref(), &[change_order.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    if change_order.deposited > 0 {
+        let transfer_ctx = CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: change_order_vault.to_account_info(),
+                to,
+                authority: change_order.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, change_order.deposited)?;
+    }
+
+    let close_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        CloseAccount {
+            account: change_order_vault.to_account_info(),
+            destination: proposer,
+            authority: change_order.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token::close_account(close_ctx)
+}
+
+#[derive(Accounts)]
+pub struct MigrateEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    /// CHECK: Legacy escrow seeded by `invoice_id` alone; discriminator checked in handler
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"escrow", invoice.invoice_id.as_bytes()],
+        bump
+    )]
+    pub legacy_escrow: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.invoice_id.as_bytes()],
+        bump,
+        constraint = legacy_escrow_token_account.owner == legacy_escrow.key() @ InvoiceError::LegacyEscrowMismatch,
+        constraint = legacy_escrow_token_account.mint == invoice.token_mint @ InvoiceError::InvalidMint
+    )]
+    pub legacy_escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = Escrow::SPACE,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = escrow,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(address = invoice.token_mint @ InvoiceError::InvalidMint)]
+    pub token_mint: Account<'info, Mint>,
+
+    /// CHECK: Invoice client receiving the legacy accounts' rent
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MarkPaid<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(debtor: Pubkey)]
+pub struct AddDebtorShare<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        has_one = creator @ InvoiceError::Unauthorized
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = DebtorShare::SPACE,
+        seeds = [b"share", invoice.key().as_ref(), debtor.as_ref()],
+        bump
+    )]
+    pub share: Account<'info, DebtorShare>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayShare<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"share", invoice.key().as_ref(), debtor.key().as_ref()],
+        bump = share.bump
+    )]
+    pub share: Account<'info, DebtorShare>,
+
+    #[account(
+        mut,
+        constraint = debtor_token_account.owner == debtor.key(),
+        constraint = debtor_token_account.mint == invoice.token_mint @ InvoiceError::InvalidMint
+    )]
+    pub debtor_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator,
+        constraint = creator_token_account.mint == invoice.token_mint @ InvoiceError::InvalidMint
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    pub debtor: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PayWithHold<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        init,
+        payer = client,
+        space = Hold::SPACE,
+        seeds = [b"hold", invoice.key().as_ref()],
+        bump
+    )]
+    pub hold: Account<'info, Hold>,
+
+    #[account(
+        init,
+        payer = client,
+        token::mint = token_mint,
+        token::authority = hold,
+        seeds = [b"hold_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub hold_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key(),
+        constraint = client_token_account.mint == token_mint.key() @ InvoiceError::InvalidMint
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(address = invoice.token_mint @ InvoiceError::InvalidMint)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawAfterHold<'info> {
+    #[account(
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        has_one = creator
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"hold", invoice.key().as_ref()],
+        bump = hold.bump
+    )]
+    pub hold: Account<'info, Hold>,
+
+    #[account(
+        mut,
+        seeds = [b"hold_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub hold_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Invoice client receiving the hold accounts' rent
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: UncheckedAccount<'info>,
+
+    pub creator: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RequestChargeback<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"hold", invoice.key().as_ref()],
+        bump = hold.bump
+    )]
+    pub hold: Account<'info, Hold>,
+
+    #[account(address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveChargeback<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"hold", invoice.key().as_ref()],
+        bump = hold.bump
+    )]
+    pub hold: Account<'info, Hold>,
+
+    #[account(
+        mut,
+        seeds = [b"hold_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub hold_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == invoice.client
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = arbiter_token_account.owner == invoice.arbiter
+    )]
+    pub arbiter_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Invoice client receiving the hold accounts' rent
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: UncheckedAccount<'info>,
+
+    /// Creator or arbiter
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+/// Pay out a hold vault, then close it and its `Hold`, sending their rent to
+/// the client who paid it
+fn release_hold<'info>(
+    token_program: &Program<'info, Token>,
+    hold: &Account<'info, Hold>,
+    hold_token_account: &Account<'info, TokenAccount>,
+    payouts: &[(AccountInfo<'info>, u64)],
+    client: AccountInfo<'info>,
+) -> Result<()> {
+    let invoice_key = hold.invoice;
+    let seeds = &[b"hold", invoice_key.as_ref(), &[hold.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    for (to, amount) in payouts {
+        if *amount == 0 {
+            continue;
+        }
+        let transfer_ctx = CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: hold_token_account.to_account_info(),
+                to: to.clone(),
+                authority: hold.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, *amount)?;
+    }
+
+    let close_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        CloseAccount {
+            account: hold_token_account.to_account_info(),
+            destination: client.clone(),
+            authority: hold.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token::close_account(close_ctx)?;
+
+    hold.close(client)
+}
+
+#[derive(Accounts)]
+pub struct PayWithVesting<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        init,
+        payer = client,
+        space = Vesting::SPACE,
+        seeds = [b"vesting", invoice.key().as_ref()],
+        bump
+    )]
+    pub vesting: Account<'info, Vesting>,
+
+    #[account(
+        init,
+        payer = client,
+        token::mint = token_mint,
+        token::authority = vesting,
+        seeds = [b"vesting_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub vesting_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key(),
+        constraint = client_token_account.mint == token_mint.key() @ InvoiceError::InvalidMint
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(address = invoice.token_mint @ InvoiceError::InvalidMint)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimVested<'info> {
+    #[account(
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        has_one = creator
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"vesting", invoice.key().as_ref()],
+        bump = vesting.bump
+    )]
+    pub vesting: Account<'info, Vesting>,
+
+    #[account(
+        mut,
+        seeds = [b"vesting_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub vesting_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Invoice client receiving the vesting accounts' rent
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: UncheckedAccount<'info>,
+
+    pub creator: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelInvoice<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelFundedEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == invoice.client
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    #[account(address = invoice.creator @ InvoiceError::Unauthorized)]
+    pub creator: Signer<'info>,
+
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == invoice.client
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeYieldConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = YieldConfig::SPACE,
+        seeds = [b"yield_config"],
+        bump
+    )]
+    pub yield_config: Account<'info, YieldConfig>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::Invoicenow>,
+
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ InvoiceError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateYieldConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"yield_config"],
+        bump = yield_config.bump,
+        has_one = admin @ InvoiceError::Unauthorized
+    )]
+    pub yield_config: Account<'info, YieldConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToStrategy<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(seeds = [b"yield_config"], bump = yield_config.bump)]
+    pub yield_config: Account<'info, YieldConfig>,
+
+    #[account(
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Must be the invoice's whitelisted yield program
+    #[account(address = invoice.yield_program @ InvoiceError::NoYieldStrategy)]
+    pub yield_program: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the yield program
+    pub yield_pool: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the yield program
+    #[account(mut)]
+    pub yield_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Created by the yield program for the escrow
+    #[account(mut)]
+    pub yield_position: UncheckedAccount<'info>,
+
+    /// Pays the position's rent
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromStrategy<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == invoice.client
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Receives the position's rent back
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: UncheckedAccount<'info>,
+
+    /// CHECK: Must be the invoice's whitelisted yield program
+    #[account(address = invoice.yield_program @ InvoiceError::NoYieldStrategy)]
+    pub yield_program: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the yield program
+    pub yield_pool: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the yield program
+    #[account(mut)]
+    pub yield_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Closed by the yield program
+    #[account(mut)]
+    pub yield_position: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+/// Instruction data for a yield program's `name` instruction. Yield programs
+/// are Anchor programs exposing `deposit(amount: u64)` and `withdraw()`, with
+/// the escrow signing as the position owner.
+fn yield_instruction_data(name: &str, args: &[u8]) -> Vec<u8> {
+    let preimage = format!("global:{name}");
+    let mut data = hashv(&[preimage.as_bytes()]).to_bytes()[..8].to_vec();
+    data.extend_from_slice(args);
+    data
+}
+
+/// Accounts for pulling an invoice's funds back out of its yield program
+struct StrategyAccounts<'info> {
+    yield_program: AccountInfo<'info>,
+    yield_pool: AccountInfo<'info>,
+    yield_vault: AccountInfo<'info>,
+    yield_position: AccountInfo<'info>,
+    creator_token_account: AccountInfo<'info>,
+    client_token_account: AccountInfo<'info>,
+    client: AccountInfo<'info>,
+}
+
+impl<'info> StrategyAccounts<'info> {
+    /// Read the accounts, in field order, from the remaining accounts of an
+    /// instruction paying out of an escrow with funds still deployed
+    fn from_remaining(invoice: &Invoice, remaining: &[AccountInfo<'info>]) -> Result<Self> {
+        let [
+            yield_program,
+            yield_pool,
+            yield_vault,
+            yield_position,
+            creator_token_account,
+            client_token_account,
+            client,
+            ..,
+        ] = remaining
+        else {
+            return err!(InvoiceError::StrategyFundsDeployed);
+        };
+
+        require_keys_eq!(
+            yield_program.key(),
+            invoice.yield_program,
+            InvoiceError::NoYieldStrategy
+        );
+        require_keys_eq!(client.key(), invoice.client, InvoiceError::Unauthorized);
+        for (account, owner) in [
+            (creator_token_account, invoice.creator),
+            (client_token_account, invoice.client),
+        ] {
+            require_keys_eq!(*account.owner, token::ID, InvoiceError::Unauthorized);
+            let token_account =
+                TokenAccount::try_deserialize(&mut &account.try_borrow_data()?[..])?;
+            require_keys_eq!(token_account.owner, owner, InvoiceError::Unauthorized);
+        }
+
+        Ok(Self {
+            yield_program: yield_program.clone(),
+            yield_pool: yield_pool.clone(),
+            yield_vault: yield_vault.clone(),
+            yield_position: yield_position.clone(),
+            creator_token_account: creator_token_account.clone(),
+            client_token_account: client_token_account.clone(),
+            client: client.clone(),
+        })
+    }
+}
+
+/// Withdraw an invoice's deployed funds back into escrow and pay out any
+/// yield per the invoice's split. A loss comes out of the escrow; the client
+/// can top it back up with another tranche.
+fn recall_strategy<'info>(
+    invoice: &mut Invoice,
+    escrow: &mut Account<'info, Escrow>,
+    escrow_token_account: &mut Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    strategy: StrategyAccounts<'info>,
+) -> Result<()> {
+    let invoice_key = escrow.invoice;
+    let principal = invoice.yield_deposited;
+
+    let ix = Instruction {
+        program_id: invoice.yield_program,
+        accounts: vec![
+            AccountMeta::new_readonly(strategy.yield_pool.key(), false),
+            AccountMeta::new(strategy.yield_vault.key(), false),
+            AccountMeta::new(strategy.yield_position.key(), false),
+            AccountMeta::new(escrow_token_account.key(), false),
+            AccountMeta::new_readonly(escrow.key(), true),
+            AccountMeta::new(strategy.client.key(), false),
+            AccountMeta::new_readonly(token_program.key(), false),
+        ],
+        data: yield_instruction_data("withdraw", &[]),
+    };
+    let seeds = &[b"escrow", invoice_key.as_ref(), &[escrow.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    let before = escrow_token_account.amount;
+    invoke_signed(
+        &ix,
+        &[
+            strategy.yield_pool,
+            strategy.yield_vault,
+            strategy.yield_position,
+            escrow_token_account.to_account_info(),
+            escrow.to_account_info(),
+            strategy.client,
+            token_program.to_account_info(),
+            strategy.yield_program,
+        ],
+        signer_seeds,
+    )?;
+    escrow_token_account.reload()?;
+    let returned = escrow_token_account.amount.saturating_sub(before);
+
+    let shortfall = principal.saturating_sub(returned);
+    escrow.deposited = escrow.deposited.saturating_sub(shortfall);
+
+    let earned = returned.saturating_sub(principal);
+    let creator_yield = earned
+        .checked_mul(invoice.yield_creator_bps as u64)
+        .unwrap()
+        .checked_div(BPS_DIVISOR)
+        .unwrap();
+    let client_yield = earned - creator_yield;
+
+    for (to, amount) in [
+        (strategy.creator_token_account, creator_yield),
+        (strategy.client_token_account, client_yield),
+    ] {
+        if amount == 0 {
+            continue;
+        }
+        let transfer_ctx = CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: escrow_token_account.to_account_info(),
+                to,
+                authority: escrow.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, amount)?;
+    }
+    escrow_token_account.reload()?;
+
+    invoice.yield_deposited = 0;
+
+    emit!(StrategyWithdrawn {
+        invoice_key,
+        yield_program: invoice.yield_program,
+        principal,
+        shortfall,
+        creator_yield,
+        client_yield,
+    });
+
+    Ok(())
+}
+
+/// Pay the creator the retainage they already earned on released milestones,
+/// so a refund only returns what was never released. Returns the amount paid.
+fn pay_retainage<'info>(
+    invoice: &mut Invoice,
+    token_program: &Program<'info, Token>,
+    escrow: &Account<'info, Escrow>,
+    escrow_token_account: &Account<'info, TokenAccount>,
+    creator_token_account: &Account<'info, TokenAccount>,
+) -> Result<u64> {
+    let amount = invoice.retainage_held;
+    if amount > 0 {
+        let invoice_key = escrow.invoice;
+        let seeds = &[b"escrow", invoice_key.as_ref(), &[escrow.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: escrow_token_account.to_account_info(),
+                to: creator_token_account.to_account_info(),
+                authority: escrow.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, amount)?;
+        invoice.retainage_held = 0;
+    }
+
+    Ok(amount)
+}
+
+/// Refund `refund` from the escrow vault. Without outside contributors it all
+/// goes to the client; otherwise the client gets its pro-rata share now and the
+/// rest is set aside for contributors to `claim_refund`. Returns the client's
+/// share and closes the escrow once nothing is left to claim.
+fn refund_escrow<'info>(
+    invoice: &mut Invoice,
+    token_program: &Program<'info, Token>,
+    escrow: &Account<'info, Escrow>,
+    escrow_token_account: &Account<'info, TokenAccount>,
+    client_token_account: &Account<'info, TokenAccount>,
+    client: AccountInfo<'info>,
+    refund: u64,
+) -> Result<u64> {
+    let invoice_key = escrow.invoice;
+    let client_share = if invoice.contributed == 0 {
+        refund
+    } else {
+        let client_deposit = escrow.deposited.saturating_sub(invoice.contributed);
+        pro_rata(refund, client_deposit, escrow.deposited)
+    };
+
+    if client_share > 0 {
+        let seeds = &[b"escrow", invoice_key.as_ref(), &[escrow.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: escrow_token_account.to_account_info(),
+                to: client_token_account.to_account_info(),
+                authority: escrow.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, client_share)?;
+    }
+
+    invoice.refund_pool = refund - client_share;
+    invoice.refunds_outstanding = if invoice.refund_pool > 0 {
+        invoice.contributor_count
+    } else {
+        0
+    };
+    if invoice.refunds_outstanding == 0 {
+        close_escrow(token_program, escrow, escrow_token_account, client)?;
+    }
+
+    Ok(client_share)
+}
+
+/// Close an emptied escrow and its vault, sending their rent to the client
+fn close_escrow<'info>(
+    token_program: &Program<'info, Token>,
+    escrow: &Account<'info, Escrow>,
+    escrow_token_account: &Account<'info, TokenAccount>,
+    client: AccountInfo<'info>,
+) -> Result<()> {
+    let invoice_key = escrow.invoice;
+    let seeds = &[b"escrow", invoice_key.as_ref(), &[escrow.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    let close_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        CloseAccount {
+            account: escrow_token_account.to_account_info(),
+            destination: client.clone(),
+            authority: escrow.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token::close_account(close_ctx)?;
+
+    escrow.close(client)
+}
+
+/// `amount * part / whole`, rounded down
+fn pro_rata(amount: u64, part: u64, whole: u64) -> u64 {
+    if whole == 0 {
+        return 0;
+    }
+    (amount as u128 * part as u128 / whole as u128) as u64
+}
+
+#[derive(Accounts)]
+pub struct RespondToInvoice<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    pub client: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RaiseDispute<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    /// Creator or client
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveDispute<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", invoice.key().as_ref()],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == invoice.client
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = arbiter_token_account.owner == invoice.arbiter
+    )]
+    pub arbiter_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Invoice client receiving the escrow accounts' rent
+    #[account(mut, address = invoice.client @ InvoiceError::Unauthorized)]
+    pub client: UncheckedAccount<'info>,
+
+    pub arbiter: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateProfile<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = UserProfile::SPACE,
+        seeds = [b"profile", owner.key().as_ref()],
+        bump
+    )]
+    pub profile: Account<'info, UserProfile>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// ============== LOTTERY ACCOUNT STRUCTS ==============
+
+#[derive(Accounts)]
+pub struct InitializeLotteryPool<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = LotteryPool::SPACE,
+        seeds = [b"lottery_pool", token_mint.key().as_ref()],
+        bump
+    )]
+    pub lottery_pool: Account<'info, LotteryPool>,
+
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = lottery_pool,
+        seeds = [b"lottery_vault", token_mint.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SeedLotteryPool<'info> {
+    #[account(
+        mut,
+        seeds = [b"lottery_pool", lottery_pool.token_mint.as_ref()],
+        bump = lottery_pool.bump
+    )]
+    pub lottery_pool: Account<'info, LotteryPool>,
+
+    #[account(
+        mut,
+        seeds = [b"lottery_vault", lottery_pool.token_mint.as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = seeder_token_account.owner == seeder.key(),
+        constraint = seeder_token_account.mint == lottery_pool.token_mint
+    )]
+    pub seeder_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub seeder: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PayWithLottery<'info> {
+    #[account(
+        mut,
+        seeds = [b"lottery_pool", lottery_pool.token_mint.as_ref()],
+        bump = lottery_pool.bump
+    )]
+    pub lottery_pool: Account<'info, LotteryPool>,
+
+    #[account(
+        mut,
+        seeds = [b"lottery_vault", lottery_pool.token_mint.as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        constraint = invoice.token_mint == lottery_pool.token_mint
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        init,
+        payer = client,
+        space = LotteryEntry::SPACE,
+        seeds = [b"lottery_entry", invoice.key().as_ref(), client.key().as_ref()],
+        bump
+    )]
+    pub lottery_entry: Account<'info, LotteryEntry>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key(),
+        constraint = client_token_account.mint == lottery_pool.token_mint
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleLottery<'info> {
+    #[account(
+        mut,
+        seeds = [b"lottery_pool", lottery_pool.token_mint.as_ref()],
+        bump = lottery_pool.bump
+    )]
+    pub lottery_pool: Account<'info, LotteryPool>,
+
+    #[account(
+        mut,
+        seeds = [b"lottery_vault", lottery_pool.token_mint.as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"lottery_entry", invoice.key().as_ref(), lottery_entry.client.as_ref()],
+        bump = lottery_entry.bump
+    )]
+    pub lottery_entry: Account<'info, LotteryEntry>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == lottery_entry.client,
+        constraint = client_token_account.mint == lottery_pool.token_mint
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator,
+        constraint = creator_token_account.mint == lottery_pool.token_mint
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    /// Anyone can settle (typically backend/crank)
+    pub settler: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ToggleLotteryPool<'info> {
+    #[account(
+        mut,
+        seeds = [b"lottery_pool", lottery_pool.token_mint.as_ref()],
+        bump = lottery_pool.bump,
+        constraint = lottery_pool.authority == authority.key()
+    )]
+    pub lottery_pool: Account<'info, LotteryPool>,
+
+    pub authority: Signer<'info>,
+}
+
+// ============== AUTOPAY ACCOUNT STRUCTS ==============
+
+#[derive(Accounts)]
+pub struct CreateAutopayMandate<'info> {
+    #[account(
+        init,
+        payer = client,
+        space = AutopayMandate::SPACE,
+        seeds = [b"autopay", client.key().as_ref(), creator.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub mandate: Account<'info, AutopayMandate>,
+
+    /// CHECK: PDA used only as the SPL delegate for the client's token account
+    #[account(
+        seeds = [b"autopay_delegate", client.key().as_ref(), creator.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub autopay_delegate: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key(),
+        constraint = client_token_account.mint == token_mint.key()
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    /// CHECK: Creator whose invoices may be paid under this mandate
+    pub creator: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAutopayMandate<'info> {
+    #[account(
+        mut,
+        close = client,
+        seeds = [b"autopay", mandate.client.as_ref(), mandate.creator.as_ref(), mandate.token_mint.as_ref()],
+        bump = mandate.bump,
+        constraint = mandate.client == client.key() @ InvoiceError::Unauthorized
+    )]
+    pub mandate: Account<'info, AutopayMandate>,
+
+    /// CHECK: This mandate's delegate PDA
+    #[account(
+        seeds = [b"autopay_delegate", mandate.client.as_ref(), mandate.creator.as_ref(), mandate.token_mint.as_ref()],
+        bump
+    )]
+    pub autopay_delegate: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key(),
+        constraint = client_token_account.mint == mandate.token_mint
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteAutopay<'info> {
+    #[account(
+        mut,
+        seeds = [b"autopay", mandate.client.as_ref(), mandate.creator.as_ref(), mandate.token_mint.as_ref()],
+        bump = mandate.bump
+    )]
+    pub mandate: Account<'info, AutopayMandate>,
+
+    /// CHECK: PDA signing as the SPL delegate for the client's token account
+    #[account(
+        seeds = [b"autopay_delegate", mandate.client.as_ref(), mandate.creator.as_ref(), mandate.token_mint.as_ref()],
+        bump
+    )]
+    pub autopay_delegate: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        constraint = invoice.creator == mandate.creator @ InvoiceError::Unauthorized,
+        constraint = invoice.token_mint == mandate.token_mint @ InvoiceError::InvalidMint
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == mandate.client,
+        constraint = client_token_account.mint == mandate.token_mint
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator,
+        constraint = creator_token_account.mint == mandate.token_mint
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    /// Anyone can execute a due autopay (typically backend/crank)
+    pub cranker: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+// ============== STREAMING ACCOUNT STRUCTS ==============
+
+#[derive(Accounts)]
+pub struct FundStream<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        init,
+        payer = client,
+        space = StreamEscrow::SPACE,
+        seeds = [b"stream", invoice.key().as_ref()],
+        bump
+    )]
+    pub stream: Account<'info, StreamEscrow>,
+
+    #[account(
+        init,
+        payer = client,
+        token::mint = token_mint,
+        token::authority = stream,
+        seeds = [b"stream_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub stream_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key(),
+        constraint = client_token_account.mint == token_mint.key()
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(address = invoice.token_mint @ InvoiceError::InvalidMint)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStreamed<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump,
+        constraint = invoice.creator == creator.key() @ InvoiceError::Unauthorized
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"stream", invoice.key().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, StreamEscrow>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub stream_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    pub creator: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelStream<'info> {
+    #[account(
+        mut,
+        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [b"stream", invoice.key().as_ref()],
+        bump = stream.bump,
+        constraint = stream.client == client.key() @ InvoiceError::Unauthorized
+    )]
+    pub stream: Account<'info, StreamEscrow>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", invoice.key().as_ref()],
+        bump
+    )]
+    pub stream_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = creator_token_account.owner == invoice.creator
+    )]
+    pub creator_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key()
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+// ============== MIGRATION ACCOUNT STRUCTS ==============
+
+#[derive(Accounts)]
+pub struct MigrateAccount<'info> {
+    /// CHECK: Discriminator and layout are checked in `upgrade_account_data`
+    #[account(mut, owner = crate::ID)]
+    pub account: UncheckedAccount<'info>,
+
+    /// Anyone can migrate an account; the payer covers any extra rent
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// === STATE ===
+
+#[account]
+pub struct Invoice {
+    /// Layout version, kept first so it sits at a fixed offset
+    pub version: u8,
+    pub creator: Pubkey,
+    pub client: Pubkey,
+    pub invoice_id: String,
+    pub amount: u64,
+    pub token_mint: Pubkey,
+    pub due_date: i64,
+    pub memo: String,
+    pub status: InvoiceStatus,
+    pub created_at: i64,
+    pub paid_at: i64,
+    pub milestones: Vec<Milestone>,
+    pub current_milestone: u8,
+    pub escrow_funded: bool,
+    pub bump: u8,
+    pub audit_hash: [u8; 32],
+    pub audit_count: u32,
+    pub require_acceptance: bool,
+    pub rejection_reason: u8,
+    pub review_period: i64,
+    pub dispute_raised_by: Pubkey,
+    pub dispute_reason: u8,
+    pub dispute_evidence: [u8; 32],
+    pub disputed_at: i64,
+    pub arbiter: Pubkey,
+    pub arbiter_fee_bps: u16,
+    pub delivery_deadline: i64,
+    pub retainage_bps: u16,
+    pub warranty_period: i64,
+    pub retainage_held: u64,
+    /// Escrow deposits made through `Contribution`s rather than by the client
+    pub contributed: u64,
+    pub contributor_count: u32,
+    /// Refund set aside for contributors to claim
+    pub refund_pool: u64,
+    pub refunds_outstanding: u32,
+    /// Sum and count of `DebtorShare`s; a split invoice is paid share by share
+    pub shares_total: u64,
+    pub share_count: u32,
+    pub shares_paid: u32,
+    /// Whitelisted program idle escrow funds may be deposited into
+    pub yield_program: Pubkey,
+    /// Creator's cut of strategy yield; the client gets the rest
+    pub yield_creator_bps: u16,
+    /// Principal currently deployed in the yield program
+    pub yield_deposited: u64,
+    /// Seconds a `pay_with_hold` payment is held open to chargebacks
+    pub hold_period: i64,
+    /// Vesting schedule for `pay_with_vesting` payments, in seconds from payment
+    pub vesting_cliff: i64,
+    pub vesting_duration: i64,
+    /// Escrow is still held under the legacy `invoice_id`-only seeds, pending
+    /// `migrate_escrow`. Only set when a funded v0 invoice is migrated.
+    pub legacy_escrow: bool,
+}
+
+impl Invoice {
+    /// Current layout version
+    pub const VERSION: u8 = 1;
+
+    /// Account size for the given variable-length contents
+    pub fn space(invoice_id: &str, memo: &str, milestones: &[Milestone]) -> usize {
+        8 + // discriminator
+        1 + // version
+        32 + // creator
+        32 + // client
+        4 + invoice_id.len() + // invoice_id string
+        8 + // amount
+        32 + // token_mint
+        8 + // due_date
+        4 + memo.len() + // memo
+        1 + // status
+        8 + // created_at
+        8 + // paid_at
+        4 + milestones.iter().map(Milestone::space).sum::<usize>() + // milestones vec
+        1 + // current_milestone
+        1 + // escrow_funded
+        1 + // bump
+        32 + // audit_hash
+        4 + // audit_count
+        1 + // require_acceptance
+        1 + // rejection_reason
+        8 + // review_period
+        32 + // dispute_raised_by
+        1 + // dispute_reason
+        32 + // dispute_evidence
+        8 + // disputed_at
+        32 + // arbiter
+        2 + // arbiter_fee_bps
+        8 + // delivery_deadline
+        2 + // retainage_bps
+        8 + // warranty_period
+        8 + // retainage_held
+        8 + // contributed
+        4 + // contributor_count
+        8 + // refund_pool
+        4 + // refunds_outstanding
+        8 + // shares_total
+        4 + // share_count
+        4 + // shares_paid
+        32 + // yield_program
+        2 + // yield_creator_bps
+        8 + // yield_deposited
+        8 + // hold_period
+        8 + // vesting_cliff
+        8 + // vesting_duration
+        1 // legacy_escrow
+    }
+
+    /// Account size for the invoice's current contents
+    pub fn current_space(&self) -> usize {
+        Self::space(&self.invoice_id, &self.memo, &self.milestones)
+    }
+
+    /// Account size once milestone `index` carries `uri` as its deliverable
+    pub fn space_with_deliverable_uri(&self, index: usize, uri: &str) -> usize {
+        let current = self.milestones.get(index).map_or(0, |m| m.deliverable_uri.len());
+        self.current_space() - current + uri.len()
+    }
+
+    /// Escrowed funds not yet released against any milestone
+    pub fn unreleased_funding(&self, escrow: &Escrow) -> u64 {
+        let released: u64 = self.milestones.iter().map(|m| m.amount_released).sum();
+        escrow.deposited.saturating_sub(released)
+    }
+
+    /// Event asking for the next tranche when the escrow cannot cover the
+    /// next open milestone
+    pub fn funding_required(&self, invoice_key: Pubkey, escrow: &Escrow) -> Option<FundingRequired> {
+        let milestone = self.milestones.get(self.current_milestone as usize)?;
+        let shortfall = milestone
+            .unreleased()
+            .saturating_sub(self.unreleased_funding(escrow));
+        (shortfall > 0).then_some(FundingRequired {
+            invoice_key,
+            milestone_index: self.current_milestone,
+            amount: shortfall,
+        })
+    }
+
+    /// Locked milestones followed by `proposed`, the result of a change order
+    pub fn amended_milestones(&self, proposed: &[Milestone]) -> Vec<Milestone> {
+        self.milestones
+            .iter()
+            .filter(|m| m.is_locked())
+            .chain(proposed)
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the invoice can currently be paid or funded
+    pub fn is_payable(&self) -> bool {
+        match self.status {
+            InvoiceStatus::Pending => !self.require_acceptance,
+            InvoiceStatus::Accepted => true,
+            _ => false,
+        }
+    }
+
+    /// Check that `payer` may pay or fund this invoice right now
+    pub fn check_payable(&self, payer: Pubkey) -> Result<()> {
+        require!(
+            matches!(self.status, InvoiceStatus::Pending | InvoiceStatus::Accepted),
+            InvoiceError::InvalidInvoiceStatus
+        );
+        require!(self.is_payable(), InvoiceError::AcceptanceRequired);
+        // Split invoices are only paid share by share
+        require!(self.share_count == 0, InvoiceError::SplitInvoice);
+        require!(
+            self.client == Pubkey::default() || self.client == payer,
+            InvoiceError::Unauthorized
+        );
+        // Paying an open invoice makes the payer its client
+        require!(
+            self.arbiter == Pubkey::default() || self.arbiter != payer,
+            InvoiceError::InvalidArbiter
+        );
+        Ok(())
+    }
+
+    /// Move to `status`, folding the transition into the rolling audit hash:
+    /// `audit_hash = sha256(prev_hash || from || to || actor || timestamp)`
+    pub fn transition(
+        &mut self,
+        invoice_key: Pubkey,
+        status: InvoiceStatus,
+        actor: Pubkey,
+        timestamp: i64,
+    ) {
+        let from = self.status.clone();
+        self.audit_hash = hashv(&[
+            &self.audit_hash,
+            &[from.clone() as u8, status.clone() as u8],
+            actor.as_ref(),
+            &timestamp.to_le_bytes(),
+        ])
+        .to_bytes();
+        self.audit_count = self.audit_count.saturating_add(1);
+        self.status = status.clone();
+
+        emit!(InvoiceStatusChanged {
+            invoice_key,
+            from,
+            to: status,
+            actor,
+            timestamp,
+            audit_hash: self.audit_hash,
+            audit_count: self.audit_count,
+        });
+    }
+}
+
+#[account]
+pub struct Escrow {
+    pub invoice: Pubkey,
+    pub deposited: u64,
+    pub released: u64,
+    pub bump: u8,
+}
 
-    #[account(
-        mut,
-        constraint = client_token_account.owner == client.key(),
-        constraint = client_token_account.mint == token_mint.key()
-    )]
-    pub client_token_account: Account<'info, TokenAccount>,
+impl Escrow {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // invoice
+        8 + // deposited
+        8 + // released
+        1; // bump
+}
 
-    /// CHECK: Token mint for payment
-    pub token_mint: AccountInfo<'info>,
+/// Yield programs escrow funds may be deposited into
+#[account]
+pub struct YieldConfig {
+    pub admin: Pubkey,
+    pub programs: Vec<Pubkey>,
+    pub bump: u8,
+}
 
-    #[account(mut)]
-    pub client: Signer<'info>,
+impl YieldConfig {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // admin
+        4 + 32 * MAX_YIELD_PROGRAMS + // programs vec
+        1; // bump
+}
 
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
+/// A direct payment held open to chargebacks until `release_at`
+#[account]
+pub struct Hold {
+    pub invoice: Pubkey,
+    pub amount: u64,
+    pub release_at: i64,
+    pub chargeback_reason: u8,
+    pub chargeback_evidence: [u8; 32],
+    pub chargeback_requested_at: i64,
+    pub bump: u8,
 }
 
-#[derive(Accounts)]
-pub struct ReleaseMilestone<'info> {
-    #[account(
-        mut,
-        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
-        bump = invoice.bump
-    )]
-    pub invoice: Account<'info, Invoice>,
+impl Hold {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // invoice
+        8 + // amount
+        8 + // release_at
+        1 + // chargeback_reason
+        32 + // chargeback_evidence
+        8 + // chargeback_requested_at
+        1; // bump
+}
 
-    #[account(
-        seeds = [b"escrow", invoice.invoice_id.as_bytes()],
-        bump = escrow.bump
-    )]
-    pub escrow: Account<'info, Escrow>,
+/// A payment vesting to the creator: nothing before `cliff_time`, then
+/// linearly from `start_time` until `end_time`
+#[account]
+pub struct Vesting {
+    pub invoice: Pubkey,
+    pub total: u64,
+    pub claimed: u64,
+    pub start_time: i64,
+    pub cliff_time: i64,
+    pub end_time: i64,
+    pub bump: u8,
+}
 
-    #[account(
-        mut,
-        seeds = [b"escrow_vault", invoice.invoice_id.as_bytes()],
-        bump
-    )]
-    pub escrow_token_account: Account<'info, TokenAccount>,
+impl Vesting {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // invoice
+        8 + // total
+        8 + // claimed
+        8 + // start_time
+        8 + // cliff_time
+        8 + // end_time
+        1; // bump
 
-    #[account(
-        mut,
-        constraint = creator_token_account.owner == invoice.creator
-    )]
-    pub creator_token_account: Account<'info, TokenAccount>,
+    /// Amount vested by `now`
+    pub fn vested_amount(&self, now: i64) -> u64 {
+        if now < self.cliff_time {
+            return 0;
+        }
+        if now >= self.end_time {
+            return self.total;
+        }
+        let elapsed = (now - self.start_time) as u128;
+        let duration = (self.end_time - self.start_time) as u128;
+        (self.total as u128 * elapsed / duration) as u64
+    }
+}
 
-    pub authority: Signer<'info>,
+/// A debtor's fixed share of a split invoice
+#[account]
+pub struct DebtorShare {
+    pub invoice: Pubkey,
+    pub debtor: Pubkey,
+    pub amount: u64,
+    pub paid: bool,
+    pub paid_at: i64,
+    pub bump: u8,
+}
 
-    pub token_program: Program<'info, Token>,
+impl DebtorShare {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // invoice
+        32 + // debtor
+        8 + // amount
+        1 + // paid
+        8 + // paid_at
+        1; // bump
 }
 
-#[derive(Accounts)]
-pub struct MarkPaid<'info> {
-    #[account(
-        mut,
-        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
-        bump = invoice.bump
-    )]
-    pub invoice: Account<'info, Invoice>,
+/// One wallet's deposits into another client's escrow
+#[account]
+pub struct Contribution {
+    pub invoice: Pubkey,
+    pub contributor: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
 
-    pub payer: Signer<'info>,
+impl Contribution {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // invoice
+        32 + // contributor
+        8 + // amount
+        1; // bump
 }
 
-#[derive(Accounts)]
-pub struct CancelInvoice<'info> {
-    #[account(
-        mut,
-        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
-        bump = invoice.bump
-    )]
-    pub invoice: Account<'info, Invoice>,
+/// Pending amendment to a funded escrow's unfinished milestones. Not
+/// versioned: cancel pending change orders before changing the `Milestone`
+/// layout.
+#[account]
+pub struct ChangeOrder {
+    pub invoice: Pubkey,
+    pub proposer: Pubkey,
+    /// Replacement for every milestone not yet (partly) released
+    pub milestones: Vec<Milestone>,
+    pub new_amount: u64,
+    /// Top-up the client already moved into the change order vault
+    pub deposited: u64,
+    /// `Escrow.released` when proposed; the order is stale once it moves
+    pub escrow_released: u64,
+    pub bump: u8,
+}
+
+impl ChangeOrder {
+    pub fn space(milestones: &[Milestone]) -> usize {
+        8 + // discriminator
+        32 + // invoice
+        32 + // proposer
+        4 + milestones.iter().map(Milestone::space).sum::<usize>() + // milestones vec
+        8 + // new_amount
+        8 + // deposited
+        8 + // escrow_released
+        1 // bump
+    }
+}
+
+#[account]
+pub struct UserProfile {
+    pub wallet: Pubkey,
+    pub name: String,
+    pub email: String,
+    pub business_name: String,
+    pub total_invoices: u64,
+    pub total_received: u64,
+    pub bump: u8,
+    pub version: u8,
+}
+
+impl UserProfile {
+    pub const SPACE: usize = 8 + 32 + (4 + 64) + (4 + 128) + (4 + 128) + 8 + 8 + 1 + 1;
+}
+
+// ============== LOTTERY STATE ==============
+
+#[account]
+pub struct LotteryPool {
+    pub authority: Pubkey,
+    pub token_mint: Pubkey,
+    pub total_balance: u64,
+    pub total_premiums_collected: u64,
+    pub total_payouts: u64,
+    pub total_entries: u64,
+    pub total_wins: u64,
+    pub house_edge_bps: u16,
+    pub min_pool_reserve_bps: u16,
+    pub max_win_pct_bps: u16,
+    pub paused: bool,
+    pub bump: u8,
+    pub version: u8,
+}
+
+impl LotteryPool {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // authority
+        32 + // token_mint
+        8 + // total_balance
+        8 + // total_premiums_collected
+        8 + // total_payouts
+        8 + // total_entries
+        8 + // total_wins
+        2 + // house_edge_bps
+        2 + // min_pool_reserve_bps
+        2 + // max_win_pct_bps
+        1 + // paused
+        1 + // bump
+        1; // version
+}
+
+#[account]
+pub struct LotteryEntry {
+    pub invoice: Pubkey,
+    pub client: Pubkey,
+    pub invoice_amount: u64,
+    pub premium_paid: u64,
+    pub win_probability_bps: u16,
+    pub status: LotteryStatus,
+    pub random_result: Option<[u8; 32]>,
+    pub created_at: i64,
+    pub resolved_at: i64,
+    pub bump: u8,
+    pub version: u8,
+}
+
+impl LotteryEntry {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // invoice
+        32 + // client
+        8 + // invoice_amount
+        8 + // premium_paid
+        2 + // win_probability_bps
+        1 + // status
+        1 + 32 + // Option<[u8; 32]>
+        8 + // created_at
+        8 + // resolved_at
+        1 + // bump
+        1; // version
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Default)]
+pub enum LotteryStatus {
+    #[default]
+    PendingVrf,
+    Won,
+    Lost,
+}
+
+// ============== AUTOPAY STATE ==============
+
+#[account]
+pub struct AutopayMandate {
+    pub client: Pubkey,
+    pub creator: Pubkey,
+    pub token_mint: Pubkey,
+    pub max_per_invoice: u64,
+    pub max_per_period: u64,
+    pub period_seconds: i64,
+    pub period_start: i64,
+    pub period_spent: u64,
+    pub expires_at: i64,
+    pub total_paid: u64,
+    pub bump: u8,
+}
+
+impl AutopayMandate {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // client
+        32 + // creator
+        32 + // token_mint
+        8 + // max_per_invoice
+        8 + // max_per_period
+        8 + // period_seconds
+        8 + // period_start
+        8 + // period_spent
+        8 + // expires_at
+        8 + // total_paid
+        1; // bump
+}
+
+// ============== STREAMING STATE ==============
+
+#[account]
+pub struct StreamEscrow {
+    pub invoice: Pubkey,
+    pub client: Pubkey,
+    pub creator: Pubkey,
+    pub deposited: u64,
+    pub withdrawn: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub bump: u8,
+}
+
+impl StreamEscrow {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // invoice
+        32 + // client
+        32 + // creator
+        8 + // deposited
+        8 + // withdrawn
+        8 + // start_time
+        8 + // end_time
+        1; // bump
+
+    /// Amount vested to the creator at `now`, linear between start and end
+    pub fn vested_amount(&self, now: i64) -> u64 {
+        if now <= self.start_time {
+            return 0;
+        }
+        if now >= self.end_time {
+            return self.deposited;
+        }
+        let elapsed = (now - self.start_time) as u128;
+        let duration = (self.end_time - self.start_time) as u128;
+        (self.deposited as u128 * elapsed / duration) as u64
+    }
+}
+
+/// Creation-time options for an invoice
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct InvoiceTerms {
+    /// Designated client; `None` leaves the invoice open to any payer
+    pub client: Option<Pubkey>,
+    /// Client must call `accept_invoice` before the invoice can be paid
+    pub require_acceptance: bool,
+    /// Seconds after submission before a milestone the client has not objected
+    /// to can be auto-released; 0 requires explicit client approval
+    pub review_period: i64,
+    /// Neutral party who settles disputes with `resolve_dispute`
+    pub arbiter: Option<Pubkey>,
+    /// Arbiter's cut of the disputed balance, in basis points
+    pub arbiter_fee_bps: u16,
+    /// After this time the client may reclaim an escrow with no milestone
+    /// under review; 0 disables reclaiming
+    pub delivery_deadline: i64,
+    /// Share of each milestone release held back until final acceptance
+    pub retainage_bps: u16,
+    /// Seconds after completion before retainage can be released without
+    /// the client
+    pub warranty_period: i64,
+    /// Whitelisted yield program idle escrow funds may be deposited into
+    pub yield_program: Option<Pubkey>,
+    /// Creator's cut of strategy yield, in basis points
+    pub yield_creator_bps: u16,
+    /// Seconds direct payments are held before the creator can withdraw;
+    /// 0 pays the creator directly
+    pub hold_period: i64,
+    /// Seconds after payment before anything vests
+    pub vesting_cliff: i64,
+    /// Seconds after payment over which it vests linearly; 0 disables vesting
+    pub vesting_duration: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct Milestone {
+    pub description: String,
+    pub amount: u64,
+    pub completed: bool,
+    pub completed_at: i64,
+    pub submitted_at: i64,
+    pub deliverable_hash: [u8; 32],
+    pub review_deadline: i64,
+    pub amount_released: u64,
+    pub deliverable_uri: String,
+}
+
+impl Milestone {
+    pub fn space(&self) -> usize {
+        4 + self.description.len() + // description
+        8 + // amount
+        1 + // completed
+        8 + // completed_at
+        8 + // submitted_at
+        32 + // deliverable_hash
+        8 + // review_deadline
+        8 + // amount_released
+        4 + self.deliverable_uri.len() // deliverable_uri
+    }
+
+    /// Amount still held in escrow for this milestone
+    pub fn unreleased(&self) -> u64 {
+        self.amount.saturating_sub(self.amount_released)
+    }
+
+    /// Whether any of this milestone has been paid out, so change orders
+    /// must leave it alone
+    pub fn is_locked(&self) -> bool {
+        self.completed || self.amount_released > 0
+    }
+}
+
+/// Portion of a milestone to release
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub enum ReleaseAmount {
+    /// Absolute token amount
+    Amount(u64),
+    /// Basis points of the milestone amount
+    Bps(u16),
+}
 
-    pub creator: Signer<'info>,
+fn validate_milestones(milestones: &[Milestone]) -> Result<()> {
+    for milestone in milestones {
+        require!(
+            milestone.description.len() <= MAX_MILESTONE_DESCRIPTION_LEN,
+            InvoiceError::MilestoneDescriptionTooLong
+        );
+        require!(milestone.amount > 0, InvoiceError::InvalidAmount);
+        require!(
+            !milestone.completed
+                && milestone.completed_at == 0
+                && milestone.submitted_at == 0
+                && milestone.review_deadline == 0
+                && milestone.amount_released == 0
+                && milestone.deliverable_uri.is_empty(),
+            InvoiceError::InvalidMilestone
+        );
+    }
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct CreateProfile<'info> {
-    #[account(
-        init,
-        payer = owner,
-        space = UserProfile::SPACE,
-        seeds = [b"profile", owner.key().as_ref()],
-        bump
-    )]
-    pub profile: Account<'info, UserProfile>,
+fn milestones_total(milestones: &[Milestone]) -> Result<u64> {
+    milestones.iter().try_fold(0u64, |total, milestone| {
+        total
+            .checked_add(milestone.amount)
+            .ok_or_else(|| error!(InvoiceError::MilestoneTotalMismatch))
+    })
+}
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Default)]
+pub enum InvoiceStatus {
+    #[default]
+    Pending,
+    EscrowFunded,
+    Paid,
+    Cancelled,
+    Disputed,
+    Streaming,
+    Accepted,
+    Rejected,
+    Resolved,
+}
 
-    pub system_program: Program<'info, System>,
+// === MIGRATION ===
+
+/// Fixed-size account whose layout carries a schema version.
+///
+/// Fields added after v0 are always appended and decode from zero bytes, so
+/// older data reads as the current layout once padded with `APPENDED_SPACE`
+/// zeros. Appending a field means bumping `VERSION` and `APPENDED_SPACE`.
+/// Invoices are variable-length and keep their version in a header instead.
+pub trait VersionedAccount: AccountSerialize + AccountDeserialize {
+    /// Current layout version
+    const VERSION: u8;
+    /// Bytes appended to the layout since v0
+    const APPENDED_SPACE: usize;
+
+    fn version(&self) -> u8;
+    fn set_version(&mut self, version: u8);
 }
 
-// ============== LOTTERY ACCOUNT STRUCTS ==============
+impl VersionedAccount for UserProfile {
+    const VERSION: u8 = 1;
+    const APPENDED_SPACE: usize = 1;
 
-#[derive(Accounts)]
-pub struct InitializeLotteryPool<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = LotteryPool::SPACE,
-        seeds = [b"lottery_pool", token_mint.key().as_ref()],
-        bump
-    )]
-    pub lottery_pool: Account<'info, LotteryPool>,
+    fn version(&self) -> u8 {
+        self.version
+    }
 
-    #[account(
-        init,
-        payer = authority,
-        token::mint = token_mint,
-        token::authority = lottery_pool,
-        seeds = [b"lottery_vault", token_mint.key().as_ref()],
-        bump
-    )]
-    pub pool_vault: Account<'info, TokenAccount>,
+    fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+}
 
-    pub token_mint: Account<'info, Mint>,
+impl VersionedAccount for LotteryPool {
+    const VERSION: u8 = 1;
+    const APPENDED_SPACE: usize = 1;
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    fn version(&self) -> u8 {
+        self.version
+    }
 
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
+    fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
 }
 
-#[derive(Accounts)]
-pub struct SeedLotteryPool<'info> {
-    #[account(
-        mut,
-        seeds = [b"lottery_pool", lottery_pool.token_mint.as_ref()],
-        bump = lottery_pool.bump
-    )]
-    pub lottery_pool: Account<'info, LotteryPool>,
+impl VersionedAccount for LotteryEntry {
+    const VERSION: u8 = 1;
+    const APPENDED_SPACE: usize = 1;
 
-    #[account(
-        mut,
-        seeds = [b"lottery_vault", lottery_pool.token_mint.as_ref()],
-        bump
-    )]
-    pub pool_vault: Account<'info, TokenAccount>,
+    fn version(&self) -> u8 {
+        self.version
+    }
 
-    #[account(
-        mut,
-        constraint = seeder_token_account.owner == seeder.key(),
-        constraint = seeder_token_account.mint == lottery_pool.token_mint
-    )]
-    pub seeder_token_account: Account<'info, TokenAccount>,
+    fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+}
 
-    #[account(mut)]
-    pub seeder: Signer<'info>,
+/// Rewrite the raw data of the account at `key` in its current layout.
+/// Returns the upgraded data along with the source and target versions.
+pub fn upgrade_account_data(key: &Pubkey, data: &[u8]) -> Result<(Vec<u8>, u8, u8)> {
+    require!(data.len() >= 8, ErrorCode::AccountDiscriminatorNotFound);
+    let discriminator = &data[..8];
+
+    if discriminator == Invoice::DISCRIMINATOR {
+        upgrade_invoice(key, data)
+    } else if discriminator == UserProfile::DISCRIMINATOR {
+        upgrade_layout::<UserProfile>(data)
+    } else if discriminator == LotteryPool::DISCRIMINATOR {
+        upgrade_layout::<LotteryPool>(data)
+    } else if discriminator == LotteryEntry::DISCRIMINATOR {
+        upgrade_layout::<LotteryEntry>(data)
+    } else {
+        err!(InvoiceError::UnsupportedAccount)
+    }
+}
 
-    pub token_program: Program<'info, Token>,
+fn upgrade_layout<T: VersionedAccount>(data: &[u8]) -> Result<(Vec<u8>, u8, u8)> {
+    finish_upgrade(read_padded::<T>(data)?)
 }
 
-#[derive(Accounts)]
-pub struct PayWithLottery<'info> {
-    #[account(
-        mut,
-        seeds = [b"lottery_pool", lottery_pool.token_mint.as_ref()],
-        bump = lottery_pool.bump
-    )]
-    pub lottery_pool: Account<'info, LotteryPool>,
+/// Read account data, zero-filling any fields appended since it was written
+fn read_padded<T: VersionedAccount>(data: &[u8]) -> Result<T> {
+    let mut padded = data.to_vec();
+    padded.resize(data.len() + T::APPENDED_SPACE, 0);
+    T::try_deserialize(&mut padded.as_slice())
+}
 
-    #[account(
-        mut,
-        seeds = [b"lottery_vault", lottery_pool.token_mint.as_ref()],
-        bump
-    )]
-    pub pool_vault: Account<'info, TokenAccount>,
+fn finish_upgrade<T: VersionedAccount>(mut account: T) -> Result<(Vec<u8>, u8, u8)> {
+    let from_version = account.version();
+    require!(from_version < T::VERSION, InvoiceError::AlreadyMigrated);
+    account.set_version(T::VERSION);
 
-    #[account(
-        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
-        bump = invoice.bump,
-        constraint = invoice.token_mint == lottery_pool.token_mint
-    )]
-    pub invoice: Account<'info, Invoice>,
+    let mut upgraded = Vec::new();
+    account.try_serialize(&mut upgraded)?;
 
-    #[account(
-        init,
-        payer = client,
-        space = LotteryEntry::SPACE,
-        seeds = [b"lottery_entry", invoice.key().as_ref(), client.key().as_ref()],
-        bump
-    )]
-    pub lottery_entry: Account<'info, LotteryEntry>,
+    Ok((upgraded, from_version, T::VERSION))
+}
 
-    #[account(
-        mut,
-        constraint = client_token_account.owner == client.key(),
-        constraint = client_token_account.mint == lottery_pool.token_mint
-    )]
-    pub client_token_account: Account<'info, TokenAccount>,
+/// Invoice fields ahead of `milestones`, unchanged since v0
+type InvoiceHead = (Pubkey, Pubkey, String, u64, Pubkey, i64, String, InvoiceStatus, i64, i64);
 
-    #[account(mut)]
-    pub client: Signer<'info>,
+/// `Milestone` layout used by v0 invoices
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct MilestoneV0 {
+    pub description: String,
+    pub amount: u64,
+    pub completed: bool,
+    pub completed_at: i64,
+}
 
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
+impl From<MilestoneV0> for Milestone {
+    fn from(milestone: MilestoneV0) -> Self {
+        // v0 milestones were only ever released in full
+        let amount_released = if milestone.completed { milestone.amount } else { 0 };
+        Milestone {
+            description: milestone.description,
+            amount: milestone.amount,
+            completed: milestone.completed,
+            completed_at: milestone.completed_at,
+            amount_released,
+            ..Default::default()
+        }
+    }
 }
 
-#[derive(Accounts)]
-pub struct SettleLottery<'info> {
-    #[account(
-        mut,
-        seeds = [b"lottery_pool", lottery_pool.token_mint.as_ref()],
-        bump = lottery_pool.bump
-    )]
-    pub lottery_pool: Account<'info, LotteryPool>,
+/// `Escrow` layout used before escrows were seeded by invoice key
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct EscrowV0 {
+    pub invoice_id: String,
+    pub bump: u8,
+}
 
-    #[account(
-        mut,
-        seeds = [b"lottery_vault", lottery_pool.token_mint.as_ref()],
-        bump
-    )]
-    pub pool_vault: Account<'info, TokenAccount>,
+/// Offset of `Invoice::version`: right after the discriminator, ahead of
+/// every variable-length field
+const INVOICE_VERSION_OFFSET: usize = 8;
+
+fn upgrade_invoice(key: &Pubkey, data: &[u8]) -> Result<(Vec<u8>, u8, u8)> {
+    // v0 invoices predate the version header. They are recognised by decoding,
+    // in the v0 layout, to the seeds of their own address; data in any other
+    // layout cannot derive the same PDA.
+    if let Some(mut invoice) = read_v0_invoice(key, data) {
+        invoice.version = Invoice::VERSION;
+        // v0 escrows could only be funded under the legacy seeds
+        invoice.legacy_escrow =
+            invoice.escrow_funded && invoice.status == InvoiceStatus::EscrowFunded;
+        let mut upgraded = Vec::new();
+        invoice.try_serialize(&mut upgraded)?;
+        return Ok((upgraded, 0, Invoice::VERSION));
+    }
 
-    #[account(
-        mut,
-        seeds = [b"invoice", invoice.creator.as_ref(), invoice.invoice_id.as_bytes()],
-        bump = invoice.bump
-    )]
-    pub invoice: Account<'info, Invoice>,
+    match data.get(INVOICE_VERSION_OFFSET) {
+        Some(&Invoice::VERSION) => err!(InvoiceError::AlreadyMigrated),
+        _ => err!(InvoiceError::UnsupportedAccount),
+    }
+}
 
-    #[account(
-        mut,
-        seeds = [b"lottery_entry", invoice.key().as_ref(), lottery_entry.client.as_ref()],
-        bump = lottery_entry.bump
-    )]
-    pub lottery_entry: Account<'info, LotteryEntry>,
+/// Decode a v0 invoice stored at `key` into the current layout, keeping every
+/// v0 field byte for byte and zero-filling the rest
+fn read_v0_invoice(key: &Pubkey, data: &[u8]) -> Option<Invoice> {
+    let body = &data[8..];
+    let mut cursor = body;
+    let (creator, _, invoice_id, _, _, _, memo, ..) = InvoiceHead::deserialize(&mut cursor).ok()?;
+    let head = &body[..body.len() - cursor.len()];
+
+    let milestones: Vec<Milestone> = Vec::<MilestoneV0>::deserialize(&mut cursor)
+        .ok()?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    // `current_milestone` and `escrow_funded` precede the bump
+    let bump = *cursor.get(2)?;
+    let address = Pubkey::create_program_address(
+        &[b"invoice", creator.as_ref(), invoice_id.as_bytes(), &[bump]],
+        &crate::ID,
+    )
+    .ok()?;
+    if address != *key {
+        return None;
+    }
 
-    #[account(
-        mut,
-        constraint = client_token_account.owner == lottery_entry.client,
-        constraint = client_token_account.mint == lottery_pool.token_mint
-    )]
-    pub client_token_account: Account<'info, TokenAccount>,
+    let mut rebuilt = Invoice::DISCRIMINATOR.to_vec();
+    rebuilt.push(0); // version
+    rebuilt.extend_from_slice(head);
+    milestones.serialize(&mut rebuilt).ok()?;
+    rebuilt.extend_from_slice(cursor);
+    let space = Invoice::space(&invoice_id, &memo, &milestones);
+    if rebuilt.len() < space {
+        rebuilt.resize(space, 0);
+    }
 
-    #[account(
-        mut,
-        constraint = creator_token_account.owner == invoice.creator,
-        constraint = creator_token_account.mint == lottery_pool.token_mint
-    )]
-    pub creator_token_account: Account<'info, TokenAccount>,
+    Invoice::try_deserialize(&mut rebuilt.as_slice()).ok()
+}
 
-    /// Anyone can settle (typically backend/crank)
-    pub settler: Signer<'info>,
+// === EVENTS ===
 
-    pub token_program: Program<'info, Token>,
+#[event]
+pub struct InvoiceCreated {
+    pub invoice_key: Pubkey,
+    pub creator: Pubkey,
+    pub invoice_id: String,
+    pub amount: u64,
+    pub due_date: i64,
 }
 
-#[derive(Accounts)]
-pub struct ToggleLotteryPool<'info> {
-    #[account(
-        mut,
-        seeds = [b"lottery_pool", lottery_pool.token_mint.as_ref()],
-        bump = lottery_pool.bump,
-        constraint = lottery_pool.authority == authority.key()
-    )]
-    pub lottery_pool: Account<'info, LotteryPool>,
+#[event]
+pub struct InvoiceUpdated {
+    pub invoice_key: Pubkey,
+    pub milestone_count: u8,
+}
 
-    pub authority: Signer<'info>,
+#[event]
+pub struct EscrowFunded {
+    pub invoice_key: Pubkey,
+    pub client: Pubkey,
+    pub amount: u64,
 }
 
-// === STATE ===
+#[event]
+pub struct FundingRequired {
+    pub invoice_key: Pubkey,
+    pub milestone_index: u8,
+    pub amount: u64,
+}
 
-#[account]
-pub struct Invoice {
-    pub creator: Pubkey,
-    pub client: Pubkey,
-    pub invoice_id: String,
+#[event]
+pub struct EscrowMigrated {
+    pub invoice_key: Pubkey,
+    pub legacy_escrow: Pubkey,
+    pub escrow: Pubkey,
     pub amount: u64,
-    pub token_mint: Pubkey,
-    pub due_date: i64,
-    pub memo: String,
-    pub status: InvoiceStatus,
-    pub created_at: i64,
-    pub paid_at: i64,
-    pub milestones: Vec<Milestone>,
-    pub current_milestone: u8,
-    pub escrow_funded: bool,
-    pub bump: u8,
 }
 
-impl Invoice {
-    pub fn space(invoice_id: &str) -> usize {
-        8 + // discriminator
-        32 + // creator
-        32 + // client
-        4 + invoice_id.len() + // invoice_id string
-        8 + // amount
-        32 + // token_mint
-        8 + // due_date
-        4 + 256 + // memo (max)
-        1 + // status
-        8 + // created_at
-        8 + // paid_at
-        4 + (10 * Milestone::SPACE) + // milestones vec (max 10)
-        1 + // current_milestone
-        1 + // escrow_funded
-        1 // bump
-    }
+#[event]
+pub struct MilestoneSubmitted {
+    pub invoice_key: Pubkey,
+    pub milestone_index: u8,
+    pub deliverable_hash: [u8; 32],
+    pub deliverable_uri: String,
+    pub submitted_at: i64,
+    pub review_deadline: i64,
 }
 
-#[account]
-pub struct Escrow {
-    pub invoice_id: String,
-    pub bump: u8,
+#[event]
+pub struct MilestoneObjected {
+    pub invoice_key: Pubkey,
+    pub milestone_index: u8,
 }
 
-impl Escrow {
-    pub const SPACE: usize = 8 + 4 + 32 + 1;
+#[event]
+pub struct ChangeOrderProposed {
+    pub invoice_key: Pubkey,
+    pub proposer: Pubkey,
+    pub milestone_count: u8,
+    pub old_amount: u64,
+    pub new_amount: u64,
 }
 
-#[account]
-pub struct UserProfile {
-    pub wallet: Pubkey,
-    pub name: String,
-    pub email: String,
-    pub business_name: String,
-    pub total_invoices: u64,
-    pub total_received: u64,
-    pub bump: u8,
+#[event]
+pub struct ChangeOrderAccepted {
+    pub invoice_key: Pubkey,
+    pub accepted_by: Pubkey,
+    pub old_amount: u64,
+    pub new_amount: u64,
 }
 
-impl UserProfile {
-    pub const SPACE: usize = 8 + 32 + (4 + 64) + (4 + 128) + (4 + 128) + 8 + 8 + 1;
+#[event]
+pub struct ChangeOrderCancelled {
+    pub invoice_key: Pubkey,
+    pub cancelled_by: Pubkey,
+    pub refunded: u64,
 }
 
-// ============== LOTTERY STATE ==============
+#[event]
+pub struct MilestoneReleased {
+    pub invoice_key: Pubkey,
+    pub milestone_index: u8,
+    pub amount: u64,
+}
 
-#[account]
-pub struct LotteryPool {
-    pub authority: Pubkey,
-    pub token_mint: Pubkey,
-    pub total_balance: u64,
-    pub total_premiums_collected: u64,
-    pub total_payouts: u64,
-    pub total_entries: u64,
-    pub total_wins: u64,
-    pub house_edge_bps: u16,
-    pub min_pool_reserve_bps: u16,
-    pub max_win_pct_bps: u16,
-    pub paused: bool,
-    pub bump: u8,
+#[event]
+pub struct MilestonePartiallyReleased {
+    pub invoice_key: Pubkey,
+    pub milestone_index: u8,
+    pub amount: u64,
+    pub amount_released: u64,
 }
 
-impl LotteryPool {
-    pub const SPACE: usize = 8 + // discriminator
-        32 + // authority
-        32 + // token_mint
-        8 + // total_balance
-        8 + // total_premiums_collected
-        8 + // total_payouts
-        8 + // total_entries
-        8 + // total_wins
-        2 + // house_edge_bps
-        2 + // min_pool_reserve_bps
-        2 + // max_win_pct_bps
-        1 + // paused
-        1; // bump
+#[event]
+pub struct RetainageReleased {
+    pub invoice_key: Pubkey,
+    pub amount: u64,
+    pub released_by: Pubkey,
 }
 
-#[account]
-pub struct LotteryEntry {
-    pub invoice: Pubkey,
-    pub client: Pubkey,
-    pub invoice_amount: u64,
-    pub premium_paid: u64,
-    pub win_probability_bps: u16,
-    pub status: LotteryStatus,
-    pub random_result: Option<[u8; 32]>,
-    pub created_at: i64,
-    pub resolved_at: i64,
-    pub bump: u8,
+#[event]
+pub struct MilestoneAutoReleased {
+    pub invoice_key: Pubkey,
+    pub milestone_index: u8,
+    pub amount: u64,
+    pub released_by: Pubkey,
 }
 
-impl LotteryEntry {
-    pub const SPACE: usize = 8 + // discriminator
-        32 + // invoice
-        32 + // client
-        8 + // invoice_amount
-        8 + // premium_paid
-        2 + // win_probability_bps
-        1 + // status
-        1 + 32 + // Option<[u8; 32]>
-        8 + // created_at
-        8 + // resolved_at
-        1; // bump
+#[event]
+pub struct DebtorShareAdded {
+    pub invoice_key: Pubkey,
+    pub debtor: Pubkey,
+    pub amount: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
-pub enum LotteryStatus {
-    PendingVrf,
-    Won,
-    Lost,
+#[event]
+pub struct SharePaid {
+    pub invoice_key: Pubkey,
+    pub debtor: Pubkey,
+    pub amount: u64,
+    pub shares_paid: u32,
+    pub share_count: u32,
 }
 
-impl Default for LotteryStatus {
-    fn default() -> Self {
-        LotteryStatus::PendingVrf
-    }
+#[event]
+pub struct YieldWhitelistUpdated {
+    pub yield_program: Pubkey,
+    pub whitelisted: bool,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
-pub struct Milestone {
-    pub description: String,
+#[event]
+pub struct StrategyDeposited {
+    pub invoice_key: Pubkey,
+    pub yield_program: Pubkey,
     pub amount: u64,
-    pub completed: bool,
-    pub completed_at: i64,
 }
 
-impl Milestone {
-    pub const SPACE: usize = 4 + 128 + 8 + 1 + 8;
+#[event]
+pub struct StrategyWithdrawn {
+    pub invoice_key: Pubkey,
+    pub yield_program: Pubkey,
+    pub principal: u64,
+    /// Principal the yield program failed to return
+    pub shortfall: u64,
+    pub creator_yield: u64,
+    pub client_yield: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
-pub enum InvoiceStatus {
-    Pending,
-    EscrowFunded,
-    Paid,
-    Cancelled,
-    Disputed,
+#[event]
+pub struct PaymentHeld {
+    pub invoice_key: Pubkey,
+    pub client: Pubkey,
+    pub amount: u64,
+    pub release_at: i64,
 }
 
-impl Default for InvoiceStatus {
-    fn default() -> Self {
-        InvoiceStatus::Pending
-    }
+#[event]
+pub struct HoldWithdrawn {
+    pub invoice_key: Pubkey,
+    pub creator: Pubkey,
+    pub amount: u64,
 }
 
-// === EVENTS ===
+#[event]
+pub struct ChargebackRequested {
+    pub invoice_key: Pubkey,
+    pub client: Pubkey,
+    pub reason_code: u8,
+    pub evidence_hash: [u8; 32],
+    pub requested_at: i64,
+}
 
 #[event]
-pub struct InvoiceCreated {
+pub struct ChargebackResolved {
     pub invoice_key: Pubkey,
-    pub creator: Pubkey,
-    pub invoice_id: String,
-    pub amount: u64,
-    pub due_date: i64,
+    pub resolved_by: Pubkey,
+    pub creator_amount: u64,
+    pub client_amount: u64,
+    pub arbiter_fee: u64,
 }
 
 #[event]
-pub struct EscrowFunded {
+pub struct PaymentVesting {
     pub invoice_key: Pubkey,
     pub client: Pubkey,
     pub amount: u64,
+    pub cliff_time: i64,
+    pub end_time: i64,
 }
 
 #[event]
-pub struct MilestoneReleased {
+pub struct VestedClaimed {
     pub invoice_key: Pubkey,
-    pub milestone_index: u8,
+    pub creator: Pubkey,
     pub amount: u64,
+    pub total_claimed: u64,
 }
 
 #[event]
@@ -1022,6 +5827,78 @@ pub struct InvoiceCancelled {
     pub invoice_key: Pubkey,
 }
 
+#[event]
+pub struct EscrowRefunded {
+    pub invoice_key: Pubkey,
+    pub client: Pubkey,
+    pub amount: u64,
+    pub contributor_refunds: u64,
+}
+
+#[event]
+pub struct EscrowContributed {
+    pub invoice_key: Pubkey,
+    pub contributor: Pubkey,
+    pub amount: u64,
+    pub total_contributed: u64,
+}
+
+#[event]
+pub struct RefundClaimed {
+    pub invoice_key: Pubkey,
+    pub contributor: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct InvoiceAccepted {
+    pub invoice_key: Pubkey,
+    pub client: Pubkey,
+}
+
+#[event]
+pub struct InvoiceRejected {
+    pub invoice_key: Pubkey,
+    pub client: Pubkey,
+    pub reason_code: u8,
+}
+
+#[event]
+pub struct DisputeRaised {
+    pub invoice_key: Pubkey,
+    pub raised_by: Pubkey,
+    pub reason_code: u8,
+    pub evidence_hash: [u8; 32],
+    pub disputed_at: i64,
+}
+
+#[event]
+pub struct DisputeResolved {
+    pub invoice_key: Pubkey,
+    pub arbiter: Pubkey,
+    pub creator_amount: u64,
+    pub client_amount: u64,
+    pub arbiter_fee: u64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub account: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+}
+
+#[event]
+pub struct InvoiceStatusChanged {
+    pub invoice_key: Pubkey,
+    pub from: InvoiceStatus,
+    pub to: InvoiceStatus,
+    pub actor: Pubkey,
+    pub timestamp: i64,
+    pub audit_hash: [u8; 32],
+    pub audit_count: u32,
+}
+
 // ============== LOTTERY EVENTS ==============
 
 #[event]
@@ -1069,6 +5946,59 @@ pub struct LotteryPoolToggled {
     pub paused: bool,
 }
 
+// ============== AUTOPAY EVENTS ==============
+
+#[event]
+pub struct AutopayMandateCreated {
+    pub mandate: Pubkey,
+    pub client: Pubkey,
+    pub creator: Pubkey,
+    pub token_mint: Pubkey,
+    pub max_per_invoice: u64,
+    pub max_per_period: u64,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct AutopayMandateCancelled {
+    pub mandate: Pubkey,
+    pub client: Pubkey,
+}
+
+#[event]
+pub struct AutopayExecuted {
+    pub mandate: Pubkey,
+    pub invoice_key: Pubkey,
+    pub client: Pubkey,
+    pub amount: u64,
+    pub paid_at: i64,
+}
+
+// ============== STREAMING EVENTS ==============
+
+#[event]
+pub struct StreamFunded {
+    pub invoice_key: Pubkey,
+    pub client: Pubkey,
+    pub amount: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+#[event]
+pub struct StreamWithdrawn {
+    pub invoice_key: Pubkey,
+    pub amount: u64,
+    pub total_withdrawn: u64,
+}
+
+#[event]
+pub struct StreamCancelled {
+    pub invoice_key: Pubkey,
+    pub paid_to_creator: u64,
+    pub refunded: u64,
+}
+
 // === ERRORS ===
 
 #[error_code]
@@ -1077,7 +6007,7 @@ pub enum InvoiceError {
     InvoiceIdTooLong,
     #[msg("Memo too long (max 256 chars)")]
     MemoTooLong,
-    #[msg("Too many milestones (max 10)")]
+    #[msg("Too many milestones (max 64)")]
     TooManyMilestones,
     #[msg("Invalid invoice status for this operation")]
     InvalidInvoiceStatus,
@@ -1097,6 +6027,110 @@ pub enum InvoiceError {
     NameTooLong,
     #[msg("Email too long (max 128 chars)")]
     EmailTooLong,
+    #[msg("Invoice must be accepted by the client first")]
+    AcceptanceRequired,
+    #[msg("Account type does not support migration")]
+    UnsupportedAccount,
+    #[msg("Account is already on the current version")]
+    AlreadyMigrated,
+    #[msg("Milestone description too long (max 128 chars)")]
+    MilestoneDescriptionTooLong,
+    #[msg("Milestones must be incomplete when added")]
+    InvalidMilestone,
+    #[msg("Milestone amounts must sum to the invoice amount")]
+    MilestoneTotalMismatch,
+    #[msg("Milestone index out of range")]
+    InvalidMilestoneIndex,
+    #[msg("Milestone already completed")]
+    MilestoneAlreadyCompleted,
+    #[msg("Review period cannot be negative")]
+    InvalidReviewPeriod,
+    #[msg("Milestone has not been submitted")]
+    MilestoneNotSubmitted,
+    #[msg("Milestone already submitted")]
+    MilestoneAlreadySubmitted,
+    #[msg("Milestone is still under client review")]
+    ReviewPeriodActive,
+    #[msg("Invoice is under dispute")]
+    InvoiceDisputed,
+    #[msg("Arbiter fee too high (max 10%)")]
+    ArbiterFeeTooHigh,
+    #[msg("Invoice has no arbiter")]
+    NoArbiter,
+    #[msg("Dispute split exceeds the escrow balance")]
+    InvalidDisputeSplit,
+    #[msg("Invalid delivery deadline")]
+    InvalidDeliveryDeadline,
+    #[msg("Delivery deadline has not passed")]
+    DeliveryDeadlineNotReached,
+    #[msg("A milestone is awaiting client review")]
+    MilestonePendingReview,
+    #[msg("Invalid release amount")]
+    InvalidReleaseAmount,
+    #[msg("Milestones were released since the change order was proposed")]
+    ChangeOrderStale,
+    #[msg("Invalid retainage terms")]
+    InvalidRetainage,
+    #[msg("No retainage is held")]
+    NoRetainageHeld,
+    #[msg("Warranty period has not ended")]
+    WarrantyPeriodActive,
+    #[msg("Escrow is already fully funded")]
+    EscrowFullyFunded,
+    #[msg("Escrow does not hold enough unreleased funds")]
+    InsufficientEscrowFunding,
+    #[msg("No refund left to claim")]
+    NothingToClaim,
+    #[msg("Not supported on an escrow with outside contributors")]
+    CrowdfundedEscrow,
+    #[msg("Split invoices are paid share by share and cannot have milestones")]
+    SplitInvoice,
+    #[msg("Debtor shares exceed the invoice amount")]
+    SharesExceedAmount,
+    #[msg("Debtor shares do not cover the invoice amount")]
+    SharesIncomplete,
+    #[msg("Share already paid")]
+    ShareAlreadyPaid,
+    #[msg("Deliverable URI too long (max 200 chars)")]
+    DeliverableUriTooLong,
+    #[msg("Yield program is not whitelisted")]
+    YieldProgramNotWhitelisted,
+    #[msg("Invoice has no yield strategy")]
+    NoYieldStrategy,
+    #[msg("Yield split exceeds 100%")]
+    InvalidYieldSplit,
+    #[msg("Escrow funds are deployed in the yield strategy; pass its accounts to withdraw them")]
+    StrategyFundsDeployed,
+    #[msg("No escrow funds are deployed in the yield strategy")]
+    NoStrategyDeposit,
+    #[msg("Invalid hold period")]
+    InvalidHoldPeriod,
+    #[msg("Invoice must be paid with pay_with_hold")]
+    HoldRequired,
+    #[msg("Hold period has not ended")]
+    HoldPeriodActive,
+    #[msg("Hold period has ended")]
+    HoldPeriodEnded,
+    #[msg("A chargeback is pending")]
+    ChargebackPending,
+    #[msg("Invalid vesting schedule")]
+    InvalidVestingSchedule,
+    #[msg("Invoice must be paid with pay_with_vesting")]
+    VestingRequired,
+    #[msg("Nothing has vested yet")]
+    NothingVested,
+    #[msg("Invoice has no designated client")]
+    NoDesignatedClient,
+    #[msg("Legacy escrow does not belong to this invoice")]
+    LegacyEscrowMismatch,
+    #[msg("Arbiter cannot be a party to the invoice")]
+    InvalidArbiter,
+    #[msg("Contribution may still be refunded")]
+    ContributionRefundable,
+    #[msg("Some debtors have already paid their share")]
+    SharesPartlyPaid,
+    #[msg("Yield program whitelist is full")]
+    YieldConfigFull,
 
     // Lottery errors
     #[msg("House edge too high (max 10%)")]
@@ -1115,4 +6149,274 @@ pub enum InvoiceError {
     InvoiceExceedsMaxWin,
     #[msg("Lottery entry already settled")]
     LotteryAlreadySettled,
+
+    // Autopay errors
+    #[msg("Mandate period must be positive")]
+    InvalidMandatePeriod,
+    #[msg("Autopay mandate has expired")]
+    MandateExpired,
+    #[msg("Payment exceeds autopay mandate limits")]
+    MandateLimitExceeded,
+    #[msg("Invoice is not yet due")]
+    InvoiceNotDue,
+    #[msg("Milestone invoices must be paid through escrow")]
+    MilestoneInvoiceNotAutopayable,
+    #[msg("Token mint does not match invoice")]
+    InvalidMint,
+
+    // Streaming errors
+    #[msg("Milestone invoices cannot be streamed")]
+    MilestoneInvoiceNotStreamable,
+    #[msg("Invalid stream schedule")]
+    InvalidStreamSchedule,
+    #[msg("Nothing available to withdraw")]
+    NothingToWithdraw,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // v0 layouts, as deployed before accounts carried a version byte
+
+    #[derive(AnchorSerialize)]
+    struct InvoiceV0 {
+        creator: Pubkey,
+        client: Pubkey,
+        invoice_id: String,
+        amount: u64,
+        token_mint: Pubkey,
+        due_date: i64,
+        memo: String,
+        status: InvoiceStatus,
+        created_at: i64,
+        paid_at: i64,
+        milestones: Vec<MilestoneV0>,
+        current_milestone: u8,
+        escrow_funded: bool,
+        bump: u8,
+    }
+
+    #[derive(AnchorSerialize)]
+    struct UserProfileV0 {
+        wallet: Pubkey,
+        name: String,
+        email: String,
+        business_name: String,
+        total_invoices: u64,
+        total_received: u64,
+        bump: u8,
+    }
+
+    #[derive(AnchorSerialize)]
+    struct LotteryPoolV0 {
+        authority: Pubkey,
+        token_mint: Pubkey,
+        total_balance: u64,
+        total_premiums_collected: u64,
+        total_payouts: u64,
+        total_entries: u64,
+        total_wins: u64,
+        house_edge_bps: u16,
+        min_pool_reserve_bps: u16,
+        max_win_pct_bps: u16,
+        paused: bool,
+        bump: u8,
+    }
+
+    #[derive(AnchorSerialize)]
+    struct LotteryEntryV0 {
+        invoice: Pubkey,
+        client: Pubkey,
+        invoice_amount: u64,
+        premium_paid: u64,
+        win_probability_bps: u16,
+        status: LotteryStatus,
+        random_result: Option<[u8; 32]>,
+        created_at: i64,
+        resolved_at: i64,
+        bump: u8,
+    }
+
+    /// Serialize a v0 account into an allocation of `space` bytes, like `init` did
+    fn fixture<T: AnchorSerialize>(discriminator: [u8; 8], account: &T, space: usize) -> Vec<u8> {
+        let mut data = discriminator.to_vec();
+        account.serialize(&mut data).unwrap();
+        assert!(data.len() <= space);
+        data.resize(space, 0);
+        data
+    }
+
+    #[test]
+    fn migrates_v0_invoice() {
+        let creator = Pubkey::new_unique();
+        let (key, bump) =
+            Pubkey::find_program_address(&[b"invoice", creator.as_ref(), b"INV-001"], &crate::ID);
+        let v0 = InvoiceV0 {
+            creator,
+            client: Pubkey::default(),
+            invoice_id: "INV-001".to_string(),
+            amount: 500,
+            token_mint: Pubkey::new_unique(),
+            due_date: 1_700_000_000,
+            memo: "Website build".to_string(),
+            status: InvoiceStatus::EscrowFunded,
+            created_at: 1_600_000_000,
+            paid_at: 0,
+            milestones: vec![
+                MilestoneV0 {
+                    description: "Design".to_string(),
+                    amount: 200,
+                    completed: true,
+                    completed_at: 1_650_000_000,
+                },
+                MilestoneV0 {
+                    description: "Build".to_string(),
+                    amount: 300,
+                    completed: false,
+                    completed_at: 0,
+                },
+            ],
+            current_milestone: 1,
+            escrow_funded: true,
+            bump,
+        };
+        // v0 allocated the maximum memo and 10 milestones
+        let space = 8 + 32 + 32 + 4 + 7 + 8 + 32 + 8 + 4 + 256 + 1 + 8 + 8 + 4 + 10 * 149 + 1 + 1 + 1;
+        let data = fixture(Invoice::DISCRIMINATOR, &v0, space);
+
+        let (upgraded, from, to) = upgrade_account_data(&key, &data).unwrap();
+        assert_eq!((from, to), (0, Invoice::VERSION));
+        assert_eq!(upgraded[INVOICE_VERSION_OFFSET], Invoice::VERSION);
+
+        let invoice = Invoice::try_deserialize(&mut upgraded.as_slice()).unwrap();
+        assert_eq!(invoice.creator, creator);
+        assert_eq!(invoice.invoice_id, "INV-001");
+        assert_eq!(invoice.memo, "Website build");
+        assert!(invoice.status == InvoiceStatus::EscrowFunded);
+        assert_eq!(invoice.milestones.len(), 2);
+        assert!(invoice.milestones[0].completed);
+        assert_eq!(invoice.milestones[0].amount_released, 200);
+        assert_eq!(invoice.milestones[1].amount, 300);
+        assert_eq!(invoice.milestones[1].amount_released, 0);
+        assert_eq!(invoice.current_milestone, 1);
+        assert_eq!(invoice.bump, bump);
+        assert_eq!(invoice.audit_hash, [0u8; 32]);
+        assert_eq!(invoice.audit_count, 0);
+        assert!(!invoice.require_acceptance);
+        assert_eq!(invoice.version, Invoice::VERSION);
+        assert!(invoice.legacy_escrow);
+
+        // Migrating twice is rejected
+        assert!(upgrade_account_data(&key, &upgraded).is_err());
+
+        // v0 data is only recognised at its own address
+        assert!(upgrade_account_data(&Pubkey::new_unique(), &data).is_err());
+    }
+
+    #[test]
+    fn migrates_v0_user_profile() {
+        let wallet = Pubkey::new_unique();
+        let v0 = UserProfileV0 {
+            wallet,
+            name: "John Doe".to_string(),
+            email: "john@example.com".to_string(),
+            business_name: "Acme Inc".to_string(),
+            total_invoices: 3,
+            total_received: 1_000,
+            bump: 255,
+        };
+        let data = fixture(UserProfile::DISCRIMINATOR, &v0, UserProfile::SPACE - 1);
+
+        let (upgraded, from, _) = upgrade_account_data(&Pubkey::new_unique(), &data).unwrap();
+        assert_eq!(from, 0);
+
+        let profile = UserProfile::try_deserialize(&mut upgraded.as_slice()).unwrap();
+        assert_eq!(profile.wallet, wallet);
+        assert_eq!(profile.business_name, "Acme Inc");
+        assert_eq!(profile.total_received, 1_000);
+        assert_eq!(profile.version, UserProfile::VERSION);
+    }
+
+    #[test]
+    fn migrates_v0_lottery_pool() {
+        let token_mint = Pubkey::new_unique();
+        let v0 = LotteryPoolV0 {
+            authority: Pubkey::new_unique(),
+            token_mint,
+            total_balance: 10_000,
+            total_premiums_collected: 500,
+            total_payouts: 200,
+            total_entries: 7,
+            total_wins: 1,
+            house_edge_bps: 500,
+            min_pool_reserve_bps: 2000,
+            max_win_pct_bps: 1000,
+            paused: true,
+            bump: 253,
+        };
+        let data = fixture(LotteryPool::DISCRIMINATOR, &v0, LotteryPool::SPACE - 1);
+
+        // The v0 allocation has no room for the version byte
+        assert!(LotteryPool::try_deserialize(&mut data.as_slice()).is_err());
+
+        let (upgraded, from, _) = upgrade_account_data(&Pubkey::new_unique(), &data).unwrap();
+        assert_eq!(from, 0);
+        assert_eq!(upgraded.len(), LotteryPool::SPACE);
+
+        let pool = LotteryPool::try_deserialize(&mut upgraded.as_slice()).unwrap();
+        assert_eq!(pool.token_mint, token_mint);
+        assert_eq!(pool.total_balance, 10_000);
+        assert!(pool.paused);
+        assert_eq!(pool.bump, 253);
+        assert_eq!(pool.version, LotteryPool::VERSION);
+    }
+
+    #[test]
+    fn migrates_v0_lottery_entry() {
+        let invoice = Pubkey::new_unique();
+        let v0 = LotteryEntryV0 {
+            invoice,
+            client: Pubkey::new_unique(),
+            invoice_amount: 1_000,
+            premium_paid: 100,
+            win_probability_bps: 950,
+            status: LotteryStatus::Won,
+            random_result: Some([7u8; 32]),
+            created_at: 1_600_000_000,
+            resolved_at: 1_600_000_060,
+            bump: 252,
+        };
+        let data = fixture(LotteryEntry::DISCRIMINATOR, &v0, LotteryEntry::SPACE - 1);
+
+        let (upgraded, from, _) = upgrade_account_data(&Pubkey::new_unique(), &data).unwrap();
+        assert_eq!(from, 0);
+
+        let entry = LotteryEntry::try_deserialize(&mut upgraded.as_slice()).unwrap();
+        assert_eq!(entry.invoice, invoice);
+        assert!(entry.status == LotteryStatus::Won);
+        assert_eq!(entry.random_result, Some([7u8; 32]));
+        assert_eq!(entry.resolved_at, 1_600_000_060);
+        assert_eq!(entry.version, LotteryEntry::VERSION);
+    }
+
+    #[test]
+    fn rejects_unknown_accounts() {
+        let data = fixture(Escrow::DISCRIMINATOR, &(Pubkey::new_unique(), 255u8), Escrow::SPACE);
+        assert!(upgrade_account_data(&Pubkey::new_unique(), &data).is_err());
+    }
+
+    #[test]
+    fn encodes_yield_instructions() {
+        use anchor_lang::InstructionData;
+
+        assert_eq!(
+            yield_instruction_data("deposit", &42u64.to_le_bytes()),
+            mock_yield::instruction::Deposit { amount: 42 }.data()
+        );
+        assert_eq!(
+            yield_instruction_data("withdraw", &[]),
+            mock_yield::instruction::Withdraw {}.data()
+        );
+    }
 }
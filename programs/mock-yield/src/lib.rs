@@ -0,0 +1,210 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+
+declare_id!("FXLPAxuMptpNgkeqVu4BbP8iUJqEiVZYBChM9d63eFPL");
+
+const BPS_DIVISOR: u64 = 10000;
+
+/// Minimal yield strategy for local testing only; it is not deployed outside
+/// localnet. Each withdrawal pays a flat `rate_bps` on the principal out of a
+/// reserve pre-funded into the pool vault, and whoever creates a pool sets its rate.
+#[program]
+pub mod mock_yield {
+    use super::*;
+
+    /// Create the pool for a mint
+    pub fn initialize_pool(ctx: Context<InitializePool>, rate_bps: u16) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.mint = ctx.accounts.mint.key();
+        pool.rate_bps = rate_bps;
+        pool.bump = ctx.bumps.pool;
+        Ok(())
+    }
+
+    /// Deposit `amount` from `owner`'s token account into a fresh position
+    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        require!(amount > 0, YieldError::InvalidAmount);
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.source.to_account_info(),
+                to: ctx.accounts.pool_vault.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            },
+        );
+        token::transfer(transfer_ctx, amount)?;
+
+        let position = &mut ctx.accounts.position;
+        position.pool = ctx.accounts.pool.key();
+        position.owner = ctx.accounts.owner.key();
+        position.principal = amount;
+        position.bump = ctx.bumps.position;
+
+        Ok(())
+    }
+
+    /// Return principal plus yield to `destination` and close the position
+    pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let principal = ctx.accounts.position.principal;
+
+        let earned = principal
+            .checked_mul(pool.rate_bps as u64)
+            .unwrap()
+            .checked_div(BPS_DIVISOR)
+            .unwrap();
+        // Never pay out more than the vault holds
+        let amount = principal
+            .checked_add(earned)
+            .unwrap()
+            .min(ctx.accounts.pool_vault.amount);
+
+        let mint = pool.mint;
+        let seeds = &[b"pool", mint.as_ref(), &[pool.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.destination.to_account_info(),
+                authority: ctx.accounts.pool.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::transfer(transfer_ctx, amount)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = Pool::SPACE,
+        seeds = [b"pool", mint.key().as_ref()],
+        bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        init,
+        payer = payer,
+        token::mint = mint,
+        token::authority = pool,
+        seeds = [b"pool_vault", mint.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(
+        seeds = [b"pool", pool.mint.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"pool_vault", pool.mint.as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = Position::SPACE,
+        seeds = [b"position", pool.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(mut, constraint = source.owner == owner.key())]
+    pub source: Account<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(
+        seeds = [b"pool", pool.mint.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"pool_vault", pool.mint.as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"position", pool.key().as_ref(), owner.key().as_ref()],
+        bump = position.bump,
+        close = rent_receiver
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+
+    /// CHECK: Receives the position's rent
+    #[account(mut)]
+    pub rent_receiver: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[account]
+pub struct Pool {
+    pub mint: Pubkey,
+    pub rate_bps: u16,
+    pub bump: u8,
+}
+
+impl Pool {
+    pub const SPACE: usize = 8 + 32 + 2 + 1;
+}
+
+#[account]
+pub struct Position {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub principal: u64,
+    pub bump: u8,
+}
+
+impl Position {
+    pub const SPACE: usize = 8 + 32 + 32 + 8 + 1;
+}
+
+#[error_code]
+pub enum YieldError {
+    #[msg("Invalid amount")]
+    InvalidAmount,
+}
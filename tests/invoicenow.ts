@@ -1,14 +1,25 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { Invoicenow } from "../target/types/invoicenow";
+import { MockYield } from "../target/types/mock_yield";
 import { expect } from "chai";
-import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { createHash } from "crypto";
+import legacyMintAuthority from "./fixtures/legacy-mint-authority.json";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import {
+  TOKEN_PROGRAM_ID,
+  createMint,
+  createAccount,
+  mintTo,
+  getAccount,
+} from "@solana/spl-token";
 
 describe("invoicenow", () => {
   const provider = anchor.AnchorProvider.env();
   anchor.setProvider(provider);
 
   const program = anchor.workspace.Invoicenow as Program<Invoicenow>;
+  const yieldProgram = anchor.workspace.MockYield as Program<MockYield>;
   const creator = provider.wallet;
 
   const invoiceId = "INV-001";
@@ -16,6 +27,111 @@ describe("invoicenow", () => {
   let invoicePda: PublicKey;
   let invoiceBump: number;
 
+  // Token accounts for instructions that move funds
+  const payer = (provider.wallet as anchor.Wallet).payer;
+  const client = Keypair.generate();
+  const arbiter = Keypair.generate();
+  let mint: PublicKey;
+  let creatorTokenAccount: PublicKey;
+  let clientTokenAccount: PublicKey;
+  let arbiterTokenAccount: PublicKey;
+
+  const defaultTerms = {
+    client: null,
+    requireAcceptance: false,
+    reviewPeriod: new anchor.BN(0),
+    arbiter: null,
+    arbiterFeeBps: 0,
+    deliveryDeadline: new anchor.BN(0),
+    retainageBps: 0,
+    warrantyPeriod: new anchor.BN(0),
+    yieldProgram: null,
+    yieldCreatorBps: 0,
+    holdPeriod: new anchor.BN(0),
+    vestingCliff: new anchor.BN(0),
+    vestingDuration: new anchor.BN(0),
+  };
+
+  const findInvoicePda = (id: string) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("invoice"), creator.publicKey.toBuffer(), Buffer.from(id)],
+      program.programId
+    )[0];
+
+  const findEscrowPdas = (invoice: PublicKey) => ({
+    escrow: PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), invoice.toBuffer()],
+      program.programId
+    )[0],
+    escrowTokenAccount: PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow_vault"), invoice.toBuffer()],
+      program.programId
+    )[0],
+  });
+
+  const findStreamPdas = (invoice: PublicKey) => ({
+    stream: PublicKey.findProgramAddressSync(
+      [Buffer.from("stream"), invoice.toBuffer()],
+      program.programId
+    )[0],
+    streamTokenAccount: PublicKey.findProgramAddressSync(
+      [Buffer.from("stream_vault"), invoice.toBuffer()],
+      program.programId
+    )[0],
+  });
+
+  const createStream = async (id: string, amount: number, start: number, end: number) => {
+    const pda = findInvoicePda(id);
+    await program.methods
+      .createInvoice(
+        id,
+        new anchor.BN(amount),
+        mint,
+        new anchor.BN(end),
+        "Hourly contract",
+        [],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const streamPdas = findStreamPdas(pda);
+    await program.methods
+      .fundStream(new anchor.BN(start), new anchor.BN(end))
+      .accounts({
+        invoice: pda,
+        ...streamPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+    return { pda, ...streamPdas };
+  };
+
+  // Validator clock, which the program's vesting math runs on
+  const chainTime = async () =>
+    (await provider.connection.getBlockTime(await provider.connection.getSlot())) as number;
+
+  const milestone = (description: string, amount: number) => ({
+    description,
+    amount: new anchor.BN(amount),
+    completed: false,
+    completedAt: new anchor.BN(0),
+    submittedAt: new anchor.BN(0),
+    deliverableHash: Array(32).fill(0),
+    reviewDeadline: new anchor.BN(0),
+    amountReleased: new anchor.BN(0),
+    deliverableUri: "",
+  });
+
   before(async () => {
     [invoicePda, invoiceBump] = PublicKey.findProgramAddressSync(
       [
@@ -25,6 +141,40 @@ describe("invoicenow", () => {
       ],
       program.programId
     );
+
+    const sig = await provider.connection.requestAirdrop(
+      client.publicKey,
+      2 * LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+
+    mint = await createMint(provider.connection, payer, creator.publicKey, null, 6);
+    creatorTokenAccount = await createAccount(
+      provider.connection,
+      payer,
+      mint,
+      creator.publicKey
+    );
+    clientTokenAccount = await createAccount(
+      provider.connection,
+      payer,
+      mint,
+      client.publicKey
+    );
+    arbiterTokenAccount = await createAccount(
+      provider.connection,
+      payer,
+      mint,
+      arbiter.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      payer,
+      mint,
+      clientTokenAccount,
+      creator.publicKey,
+      10_000_000_000
+    );
   });
 
   it("Creates an invoice", async () => {
@@ -40,7 +190,8 @@ describe("invoicenow", () => {
         tokenMint,
         dueDate,
         memo,
-        [] // No milestones for simple invoice
+        [], // No milestones for simple invoice
+        defaultTerms
       )
       .accounts({
         invoice: invoicePda,
@@ -75,24 +226,9 @@ describe("invoicenow", () => {
     const tokenMint = new PublicKey("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
 
     const milestones = [
-      {
-        description: "Design mockups",
-        amount: new anchor.BN(100_000_000),
-        completed: false,
-        completedAt: new anchor.BN(0),
-      },
-      {
-        description: "Frontend development",
-        amount: new anchor.BN(200_000_000),
-        completed: false,
-        completedAt: new anchor.BN(0),
-      },
-      {
-        description: "Backend + deployment",
-        amount: new anchor.BN(200_000_000),
-        completed: false,
-        completedAt: new anchor.BN(0),
-      },
+      milestone("Design mockups", 100_000_000),
+      milestone("Frontend development", 200_000_000),
+      milestone("Backend + deployment", 200_000_000),
     ];
 
     const tx = await program.methods
@@ -102,7 +238,8 @@ describe("invoicenow", () => {
         tokenMint,
         dueDate,
         memo,
-        milestones
+        milestones,
+        defaultTerms
       )
       .accounts({
         invoice: milestonePda,
@@ -135,7 +272,7 @@ describe("invoicenow", () => {
 
     // Create invoice first
     await program.methods
-      .createInvoice(paidInvoiceId, amount, tokenMint, dueDate, "Quick job", [])
+      .createInvoice(paidInvoiceId, amount, tokenMint, dueDate, "Quick job", [], defaultTerms)
       .accounts({
         invoice: paidPda,
         creator: creator.publicKey,
@@ -158,6 +295,17 @@ describe("invoicenow", () => {
     const invoice = await program.account.invoice.fetch(paidPda);
     expect(invoice.status).to.deep.equal({ paid: {} });
     expect(invoice.paidAt.toNumber()).to.be.greaterThan(0);
+
+    // Audit trail: sha256(prev_hash || from || to || actor || timestamp)
+    const timestamp = invoice.paidAt.toArrayLike(Buffer, "le", 8);
+    const expectedHash = createHash("sha256")
+      .update(Buffer.alloc(32))
+      .update(Buffer.from([0, 2])) // Pending -> Paid
+      .update(creator.publicKey.toBuffer())
+      .update(timestamp)
+      .digest();
+    expect(invoice.auditCount).to.equal(1);
+    expect(Buffer.from(invoice.auditHash)).to.deep.equal(expectedHash);
   });
 
   it("Creates user profile", async () => {
@@ -200,7 +348,7 @@ describe("invoicenow", () => {
 
     // Create invoice
     await program.methods
-      .createInvoice(cancelInvoiceId, amount, tokenMint, dueDate, "Cancelled", [])
+      .createInvoice(cancelInvoiceId, amount, tokenMint, dueDate, "Cancelled", [], defaultTerms)
       .accounts({
         invoice: cancelPda,
         creator: creator.publicKey,
@@ -222,4 +370,2402 @@ describe("invoicenow", () => {
     const invoice = await program.account.invoice.fetch(cancelPda);
     expect(invoice.status).to.deep.equal({ cancelled: {} });
   });
+
+  it("Pays a due invoice through an autopay mandate", async () => {
+    const autopayInvoiceId = "INV-AUTO-001";
+    const autopayPda = findInvoicePda(autopayInvoiceId);
+    const amount = new anchor.BN(40_000_000);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) - 60); // already due
+
+    await program.methods
+      .createInvoice(autopayInvoiceId, amount, mint, dueDate, "Monthly retainer", [], defaultTerms)
+      .accounts({
+        invoice: autopayPda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const [mandatePda] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("autopay"),
+        client.publicKey.toBuffer(),
+        creator.publicKey.toBuffer(),
+        mint.toBuffer(),
+      ],
+      program.programId
+    );
+    const [delegatePda] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("autopay_delegate"),
+        client.publicKey.toBuffer(),
+        creator.publicKey.toBuffer(),
+        mint.toBuffer(),
+      ],
+      program.programId
+    );
+
+    await program.methods
+      .createAutopayMandate(
+        new anchor.BN(50_000_000),
+        new anchor.BN(100_000_000),
+        new anchor.BN(86400 * 30),
+        new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 365),
+        new anchor.BN(1_000_000_000)
+      )
+      .accounts({
+        mandate: mandatePda,
+        autopayDelegate: delegatePda,
+        clientTokenAccount,
+        tokenMint: mint,
+        creator: creator.publicKey,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const before = await getAccount(provider.connection, creatorTokenAccount);
+
+    const tx = await program.methods
+      .executeAutopay()
+      .accounts({
+        mandate: mandatePda,
+        autopayDelegate: delegatePda,
+        invoice: autopayPda,
+        clientTokenAccount,
+        creatorTokenAccount,
+        cranker: creator.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    console.log("Execute autopay tx:", tx);
+
+    const after = await getAccount(provider.connection, creatorTokenAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(40_000_000);
+
+    const invoice = await program.account.invoice.fetch(autopayPda);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+    expect(invoice.client.toString()).to.equal(client.publicKey.toString());
+
+    const mandate = await program.account.autopayMandate.fetch(mandatePda);
+    expect(mandate.periodSpent.toNumber()).to.equal(40_000_000);
+
+    // Cancelling revokes the mandate's delegate approval
+    await program.methods
+      .cancelAutopayMandate()
+      .accounts({
+        mandate: mandatePda,
+        autopayDelegate: delegatePda,
+        clientTokenAccount,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+    const clientAccount = await getAccount(provider.connection, clientTokenAccount);
+    expect(clientAccount.delegate).to.be.null;
+  });
+
+  it("Streams an invoice the creator withdraws as it vests", async () => {
+    const now = await chainTime();
+    const { pda, stream, streamTokenAccount } = await createStream(
+      "INV-STREAM-002",
+      6_000_000,
+      now,
+      now + 6
+    );
+
+    let invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ streaming: {} });
+
+    const withdraw = () =>
+      program.methods
+        .withdrawStreamed()
+        .accounts({
+          invoice: pda,
+          stream,
+          streamTokenAccount,
+          creatorTokenAccount,
+          creator: creator.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+    // Part way through, the creator gets exactly what has vested so far
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    const before = await getAccount(provider.connection, creatorTokenAccount);
+    await withdraw();
+    const partial = await getAccount(provider.connection, creatorTokenAccount);
+    const streamed = await program.account.streamEscrow.fetch(stream);
+    const firstWithdrawal = Number(partial.amount) - Number(before.amount);
+    expect(firstWithdrawal).to.equal(streamed.withdrawn.toNumber());
+    expect(firstWithdrawal).to.be.greaterThan(0);
+    expect(firstWithdrawal).to.be.lessThan(6_000_000);
+
+    // Once the stream ends, the rest is paid and the invoice settles
+    while ((await chainTime()) < now + 6) {
+      await new Promise((resolve) => setTimeout(resolve, 1000));
+    }
+    await withdraw();
+    const after = await getAccount(provider.connection, creatorTokenAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(6_000_000);
+
+    const vault = await getAccount(provider.connection, streamTokenAccount);
+    expect(Number(vault.amount)).to.equal(0);
+    invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+
+    try {
+      await withdraw();
+      expect.fail("a settled stream has nothing left to withdraw");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidInvoiceStatus");
+    }
+  });
+
+  it("Streams an invoice and refunds the unvested remainder on cancel", async () => {
+    // 36M over an hour vests 10k per second; ten minutes have already passed
+    const now = await chainTime();
+    const start = now - 600;
+    const { pda, stream, streamTokenAccount } = await createStream(
+      "INV-STREAM-001",
+      36_000_000,
+      start,
+      start + 3600
+    );
+
+    const creatorBefore = await getAccount(provider.connection, creatorTokenAccount);
+    const clientBefore = await getAccount(provider.connection, clientTokenAccount);
+    await program.methods
+      .cancelStream()
+      .accounts({
+        invoice: pda,
+        stream,
+        streamTokenAccount,
+        creatorTokenAccount,
+        clientTokenAccount,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ cancelled: {} });
+
+    const vault = await getAccount(provider.connection, streamTokenAccount);
+    expect(Number(vault.amount)).to.equal(0);
+
+    const creatorAfter = await getAccount(provider.connection, creatorTokenAccount);
+    const clientAfter = await getAccount(provider.connection, clientTokenAccount);
+    const paidToCreator = Number(creatorAfter.amount) - Number(creatorBefore.amount);
+    const refunded = Number(clientAfter.amount) - Number(clientBefore.amount);
+    expect(paidToCreator + refunded).to.equal(36_000_000);
+
+    // The stream's end is moved to the cancel time; the split matches what
+    // had vested by then to within a second's worth
+    const cancelled = await program.account.streamEscrow.fetch(stream);
+    const vested = 10_000 * (cancelled.endTime.toNumber() - start);
+    expect(Math.abs(paidToCreator - vested)).to.be.at.most(10_000);
+    expect(cancelled.withdrawn.toNumber()).to.equal(paidToCreator);
+  });
+
+  it("Requires client acceptance before payment", async () => {
+    const acceptInvoiceId = "INV-ACCEPT-001";
+    const acceptPda = findInvoicePda(acceptInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 14);
+
+    // Open invoices could be claimed by whoever accepts first
+    try {
+      await program.methods
+        .createInvoice(acceptInvoiceId, new anchor.BN(10_000_000), mint, dueDate, "Needs sign-off", [], {
+          ...defaultTerms,
+          requireAcceptance: true,
+        })
+        .accounts({
+          invoice: acceptPda,
+          creator: creator.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      expect.fail("acceptance needs a designated client");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("NoDesignatedClient");
+    }
+
+    await program.methods
+      .createInvoice(acceptInvoiceId, new anchor.BN(10_000_000), mint, dueDate, "Needs sign-off", [], {
+        ...defaultTerms,
+        client: client.publicKey,
+        requireAcceptance: true,
+      })
+      .accounts({
+        invoice: acceptPda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    try {
+      await program.methods
+        .markPaid("5abc123...fake_signature")
+        .accounts({ invoice: acceptPda, payer: creator.publicKey })
+        .rpc();
+      expect.fail("payment before acceptance should fail");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("AcceptanceRequired");
+    }
+
+    await program.methods
+      .acceptInvoice()
+      .accounts({ invoice: acceptPda, client: client.publicKey })
+      .signers([client])
+      .rpc();
+
+    const invoice = await program.account.invoice.fetch(acceptPda);
+    expect(invoice.status).to.deep.equal({ accepted: {} });
+    expect(invoice.client.toString()).to.equal(client.publicKey.toString());
+  });
+
+  it("Lets the designated client reject an invoice", async () => {
+    const rejectInvoiceId = "INV-REJECT-001";
+    const rejectPda = findInvoicePda(rejectInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 14);
+
+    await program.methods
+      .createInvoice(rejectInvoiceId, new anchor.BN(10_000_000), mint, dueDate, "Disputed scope", [], {
+        ...defaultTerms,
+        client: client.publicKey,
+        requireAcceptance: true,
+      })
+      .accounts({
+        invoice: rejectPda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .rejectInvoice(3)
+      .accounts({ invoice: rejectPda, client: client.publicKey })
+      .signers([client])
+      .rpc();
+
+    const invoice = await program.account.invoice.fetch(rejectPda);
+    expect(invoice.status).to.deep.equal({ rejected: {} });
+    expect(invoice.rejectionReason).to.equal(3);
+  });
+
+  it("Sizes invoice accounts to their contents", async () => {
+    const sizedInvoiceId = "INV-SIZED-001";
+    const sizedPda = findInvoicePda(sizedInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(sizedInvoiceId, new anchor.BN(300_000_000), mint, dueDate, "Hi", [], defaultTerms)
+      .accounts({
+        invoice: sizedPda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const initialSize = (await provider.connection.getAccountInfo(sizedPda)).data.length;
+
+    await program.methods
+      .updateMemo("A much longer memo describing the engagement")
+      .accounts({
+        invoice: sizedPda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const milestones = [
+      milestone("Kickoff", 100_000_000),
+      milestone("Delivery", 200_000_000),
+    ];
+    await program.methods
+      .addMilestones(milestones)
+      .accounts({
+        invoice: sizedPda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const info = await provider.connection.getAccountInfo(sizedPda);
+    const memoGrowth = "A much longer memo describing the engagement".length - "Hi".length;
+    const milestoneSpace = milestones
+      .map((m) => 4 + m.description.length + 8 + 1 + 8 + 8 + 32 + 8 + 8 + 4)
+      .reduce((a, b) => a + b, 0);
+    expect(info.data.length).to.equal(initialSize + memoGrowth + milestoneSpace);
+
+    const invoice = await program.account.invoice.fetch(sizedPda);
+    expect(invoice.milestones.length).to.equal(2);
+  });
+
+  it("Keeps escrows separate for creators sharing an invoice ID", async () => {
+    const sharedInvoiceId = "INV-SHARED";
+    const otherCreator = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(
+      otherCreator.publicKey,
+      LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+    const otherCreatorTokenAccount = await createAccount(
+      provider.connection,
+      payer,
+      mint,
+      otherCreator.publicKey
+    );
+
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+    const milestones = [milestone("Whole project", 20_000_000)];
+
+    for (const [owner, ownerTokenAccount, signers] of [
+      [creator.publicKey, creatorTokenAccount, []],
+      [otherCreator.publicKey, otherCreatorTokenAccount, [otherCreator]],
+    ] as [PublicKey, PublicKey, Keypair[]][]) {
+      const [sharedPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("invoice"), owner.toBuffer(), Buffer.from(sharedInvoiceId)],
+        program.programId
+      );
+      const [escrowPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("escrow"), sharedPda.toBuffer()],
+        program.programId
+      );
+      const [escrowVault] = PublicKey.findProgramAddressSync(
+        [Buffer.from("escrow_vault"), sharedPda.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createInvoice(sharedInvoiceId, new anchor.BN(20_000_000), mint, dueDate, "Shared ID", milestones, defaultTerms)
+        .accounts({
+          invoice: sharedPda,
+          creator: owner,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers(signers)
+        .rpc();
+
+      await program.methods
+        .fundEscrow(new anchor.BN(20_000_000))
+        .accounts({
+          invoice: sharedPda,
+          escrow: escrowPda,
+          escrowTokenAccount: escrowVault,
+          clientTokenAccount,
+          tokenMint: mint,
+          client: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([client])
+        .rpc();
+
+      const before = await getAccount(provider.connection, ownerTokenAccount);
+
+      await program.methods
+        .releaseMilestone(0)
+        .accounts({
+          invoice: sharedPda,
+          escrow: escrowPda,
+          escrowTokenAccount: escrowVault,
+          creatorTokenAccount: ownerTokenAccount,
+          authority: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([client])
+        .rpc();
+
+      const after = await getAccount(provider.connection, ownerTokenAccount);
+      expect(Number(after.amount) - Number(before.amount)).to.equal(20_000_000);
+
+      const invoice = await program.account.invoice.fetch(sharedPda);
+      expect(invoice.status).to.deep.equal({ paid: {} });
+    }
+  });
+
+  describe("escrow funding validation", () => {
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    const createMilestoneInvoice = async (id: string, amount: number, milestones: any[]) => {
+      const pda = findInvoicePda(id);
+      await program.methods
+        .createInvoice(id, new anchor.BN(amount), mint, dueDate, "Escrow checks", milestones, defaultTerms)
+        .accounts({
+          invoice: pda,
+          creator: creator.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      return pda;
+    };
+
+    const fundEscrow = (pda: PublicKey, amount: number, tokenMint: PublicKey, source: PublicKey) =>
+      program.methods
+        .fundEscrow(new anchor.BN(amount))
+        .accounts({
+          invoice: pda,
+          ...findEscrowPdas(pda),
+          clientTokenAccount: source,
+          tokenMint,
+          client: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([client])
+        .rpc();
+
+    it("Rejects milestones that exceed the invoice amount", async () => {
+      try {
+        await createMilestoneInvoice("INV-VAL-001", 10_000_000, [
+          milestone("Too much", 20_000_000),
+        ]);
+        expect.fail("milestones above the invoice amount should fail");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("MilestoneTotalMismatch");
+      }
+    });
+
+    it("Rejects funding when milestones do not cover the invoice", async () => {
+      const pda = await createMilestoneInvoice("INV-VAL-002", 10_000_000, [
+        milestone("Only half", 5_000_000),
+      ]);
+      try {
+        await fundEscrow(pda, 10_000_000, mint, clientTokenAccount);
+        expect.fail("funding an under-specified escrow should fail");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("MilestoneTotalMismatch");
+      }
+    });
+
+    it("Rejects funding with a different mint", async () => {
+      const pda = await createMilestoneInvoice("INV-VAL-003", 10_000_000, [
+        milestone("Deliverable", 10_000_000),
+      ]);
+      const otherMint = await createMint(provider.connection, payer, creator.publicKey, null, 6);
+      const otherTokenAccount = await createAccount(
+        provider.connection,
+        payer,
+        otherMint,
+        client.publicKey
+      );
+      await mintTo(provider.connection, payer, otherMint, otherTokenAccount, creator.publicKey, 10_000_000);
+
+      try {
+        await fundEscrow(pda, 10_000_000, otherMint, otherTokenAccount);
+        expect.fail("funding with the wrong mint should fail");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InvalidMint");
+      }
+    });
+
+    it("Only pulls the invoice amount when overfunding", async () => {
+      const pda = await createMilestoneInvoice("INV-VAL-004", 10_000_000, [
+        milestone("Deliverable", 10_000_000),
+      ]);
+      const before = await getAccount(provider.connection, clientTokenAccount);
+
+      await fundEscrow(pda, 25_000_000, mint, clientTokenAccount);
+
+      const after = await getAccount(provider.connection, clientTokenAccount);
+      expect(Number(before.amount) - Number(after.amount)).to.equal(10_000_000);
+
+      const escrow = await program.account.escrow.fetch(findEscrowPdas(pda).escrow);
+      expect(escrow.deposited.toNumber()).to.equal(10_000_000);
+    });
+  });
+
+  it("Releases milestones out of order", async () => {
+    const outOfOrderId = "INV-ORDER-001";
+    const pda = findInvoicePda(outOfOrderId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        outOfOrderId,
+        new anchor.BN(30_000_000),
+        mint,
+        dueDate,
+        "Parallel deliverables",
+        [milestone("Logo", 10_000_000), milestone("Website", 20_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .fundEscrow(new anchor.BN(30_000_000))
+      .accounts({
+        invoice: pda,
+        ...findEscrowPdas(pda),
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const release = (index: number) =>
+      program.methods
+        .releaseMilestone(index)
+        .accounts({
+          invoice: pda,
+          ...findEscrowPdas(pda),
+          creatorTokenAccount,
+          authority: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([client])
+        .rpc();
+
+    await release(1);
+    let invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.milestones[1].completed).to.equal(true);
+    expect(invoice.milestones[0].completed).to.equal(false);
+    expect(invoice.currentMilestone).to.equal(0);
+    expect(invoice.status).to.deep.equal({ escrowFunded: {} });
+
+    await release(0);
+    invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.currentMilestone).to.equal(2);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+  });
+
+  it("Auto-releases a submitted milestone only after its review deadline", async () => {
+    const reviewInvoiceId = "INV-REVIEW-001";
+    const pda = findInvoicePda(reviewInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        reviewInvoiceId,
+        new anchor.BN(15_000_000),
+        mint,
+        dueDate,
+        "Reviewed delivery",
+        [milestone("Report", 15_000_000)],
+        { ...defaultTerms, reviewPeriod: new anchor.BN(86400 * 3) }
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .fundEscrow(new anchor.BN(15_000_000))
+      .accounts({
+        invoice: pda,
+        ...findEscrowPdas(pda),
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const releaseAccounts = (authority: PublicKey) => ({
+      invoice: pda,
+      ...findEscrowPdas(pda),
+      creatorTokenAccount,
+      authority,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    });
+
+    try {
+      await program.methods
+        .autoReleaseMilestone(0)
+        .accounts(releaseAccounts(creator.publicKey))
+        .rpc();
+      expect.fail("an unsubmitted milestone cannot be auto-released");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("MilestoneNotSubmitted");
+    }
+
+    const deliverableHash = Array.from(createHash("sha256").update("report.pdf").digest());
+    await program.methods
+      .submitMilestone(0, deliverableHash, "ipfs://bafy-report")
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    let invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.milestones[0].reviewDeadline.toNumber()).to.equal(
+      invoice.milestones[0].submittedAt.toNumber() + 86400 * 3
+    );
+
+    try {
+      await program.methods
+        .autoReleaseMilestone(0)
+        .accounts(releaseAccounts(creator.publicKey))
+        .rpc();
+      expect.fail("cannot auto-release during the review period");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ReviewPeriodActive");
+    }
+
+    try {
+      await program.methods
+        .releaseMilestone(0)
+        .accounts(releaseAccounts(creator.publicKey))
+        .rpc();
+      expect.fail("only the client can release directly");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+
+    // The client approves by releasing before the deadline
+    await program.methods
+      .releaseMilestone(0)
+      .accounts(releaseAccounts(client.publicKey))
+      .signers([client])
+      .rpc();
+
+    invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+    expect(invoice.milestones[0].deliverableHash).to.deep.equal(deliverableHash);
+  });
+
+  it("Freezes milestone releases once a dispute is raised", async () => {
+    const disputeInvoiceId = "INV-DISPUTE-001";
+    const pda = findInvoicePda(disputeInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        disputeInvoiceId,
+        new anchor.BN(12_000_000),
+        mint,
+        dueDate,
+        "Contested delivery",
+        [milestone("Prototype", 12_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .fundEscrow(new anchor.BN(12_000_000))
+      .accounts({
+        invoice: pda,
+        ...findEscrowPdas(pda),
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const outsider = Keypair.generate();
+    const evidenceHash = Array.from(createHash("sha256").update("emails.zip").digest());
+    try {
+      await program.methods
+        .raiseDispute(1, evidenceHash)
+        .accounts({ invoice: pda, authority: outsider.publicKey })
+        .signers([outsider])
+        .rpc();
+      expect.fail("only the parties can raise a dispute");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+
+    await program.methods
+      .raiseDispute(1, evidenceHash)
+      .accounts({ invoice: pda, authority: client.publicKey })
+      .signers([client])
+      .rpc();
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ disputed: {} });
+    expect(invoice.disputeRaisedBy.toString()).to.equal(client.publicKey.toString());
+    expect(invoice.disputeReason).to.equal(1);
+    expect(invoice.disputeEvidence).to.deep.equal(evidenceHash);
+
+    try {
+      await program.methods
+        .releaseMilestone(0)
+        .accounts({
+          invoice: pda,
+          ...findEscrowPdas(pda),
+          creatorTokenAccount,
+          authority: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([client])
+        .rpc();
+      expect.fail("releases are frozen during a dispute");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvoiceDisputed");
+    }
+
+    // With no arbiter, the parties settle by cancelling together
+    const before = await getAccount(provider.connection, clientTokenAccount);
+    await program.methods
+      .cancelFundedEscrow()
+      .accounts({
+        invoice: pda,
+        ...findEscrowPdas(pda),
+        clientTokenAccount,
+        creatorTokenAccount,
+        creator: creator.publicKey,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+    const after = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(12_000_000);
+    const settled = await program.account.invoice.fetch(pda);
+    expect(settled.status).to.deep.equal({ cancelled: {} });
+  });
+
+  it("Lets the arbiter split a disputed escrow", async () => {
+    const settledInvoiceId = "INV-DISPUTE-002";
+    const pda = findInvoicePda(settledInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    // A party cannot rule on its own dispute
+    try {
+      await program.methods
+        .createInvoice(
+          settledInvoiceId,
+          new anchor.BN(10_000_000),
+          mint,
+          dueDate,
+          "Arbitrated delivery",
+          [milestone("Half", 5_000_000), milestone("Rest", 5_000_000)],
+          { ...defaultTerms, arbiter: creator.publicKey }
+        )
+        .accounts({
+          invoice: pda,
+          creator: creator.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      expect.fail("creator cannot arbitrate");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidArbiter");
+    }
+
+    await program.methods
+      .createInvoice(
+        settledInvoiceId,
+        new anchor.BN(10_000_000),
+        mint,
+        dueDate,
+        "Arbitrated delivery",
+        [milestone("Half", 5_000_000), milestone("Rest", 5_000_000)],
+        { ...defaultTerms, arbiter: arbiter.publicKey, arbiterFeeBps: 500 }
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(10_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    await program.methods
+      .raiseDispute(2, Array(32).fill(0))
+      .accounts({ invoice: pda, authority: creator.publicKey })
+      .rpc();
+
+    const resolveAccounts = {
+      invoice: pda,
+      ...escrowPdas,
+      creatorTokenAccount,
+      clientTokenAccount,
+      arbiterTokenAccount,
+      client: client.publicKey,
+      arbiter: arbiter.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    // 5% fee leaves 9.5M to split
+    try {
+      await program.methods
+        .resolveDispute(new anchor.BN(9_600_000))
+        .accounts(resolveAccounts)
+        .signers([arbiter])
+        .rpc();
+      expect.fail("split cannot exceed the balance after fees");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidDisputeSplit");
+    }
+
+    const creatorBefore = await getAccount(provider.connection, creatorTokenAccount);
+    const clientBefore = await getAccount(provider.connection, clientTokenAccount);
+
+    await program.methods
+      .resolveDispute(new anchor.BN(6_000_000))
+      .accounts(resolveAccounts)
+      .signers([arbiter])
+      .rpc();
+
+    const creatorAfter = await getAccount(provider.connection, creatorTokenAccount);
+    const clientAfter = await getAccount(provider.connection, clientTokenAccount);
+    const arbiterAfter = await getAccount(provider.connection, arbiterTokenAccount);
+    expect(Number(creatorAfter.amount) - Number(creatorBefore.amount)).to.equal(6_000_000);
+    expect(Number(clientAfter.amount) - Number(clientBefore.amount)).to.equal(3_500_000);
+    expect(Number(arbiterAfter.amount)).to.equal(500_000);
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ resolved: {} });
+    expect(await provider.connection.getAccountInfo(escrowPdas.escrowTokenAccount)).to.equal(null);
+  });
+
+  it("Refunds a funded escrow when both parties cancel", async () => {
+    const mutualInvoiceId = "INV-MUTUAL-001";
+    const pda = findInvoicePda(mutualInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        mutualInvoiceId,
+        new anchor.BN(8_000_000),
+        mint,
+        dueDate,
+        "Called off",
+        [milestone("Draft", 3_000_000), milestone("Final", 5_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(8_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    await program.methods
+      .releaseMilestone(0)
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        creatorTokenAccount,
+        authority: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+
+    const before = await getAccount(provider.connection, clientTokenAccount);
+
+    await program.methods
+      .cancelFundedEscrow()
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        creatorTokenAccount,
+        creator: creator.publicKey,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+
+    const after = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(5_000_000);
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ cancelled: {} });
+    expect(invoice.escrowFunded).to.equal(false);
+    expect(await provider.connection.getAccountInfo(escrowPdas.escrow)).to.equal(null);
+  });
+
+  it("Lets the client reclaim escrow after the delivery deadline", async () => {
+    const lapsedInvoiceId = "INV-LAPSED-001";
+    const pda = findInvoicePda(lapsedInvoiceId);
+    const now = Math.floor(Date.now() / 1000);
+
+    await program.methods
+      .createInvoice(
+        lapsedInvoiceId,
+        new anchor.BN(4_000_000),
+        mint,
+        new anchor.BN(now + 86400 * 30),
+        "Abandoned project",
+        [milestone("Everything", 4_000_000)],
+        // Deadline already behind us so the test doesn't have to wait
+        { ...defaultTerms, deliveryDeadline: new anchor.BN(now - 60) }
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(4_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    await program.methods
+      .submitMilestone(0, null, "")
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const reclaim = () =>
+      program.methods
+        .reclaimEscrow()
+        .accounts({
+          invoice: pda,
+          ...escrowPdas,
+          clientTokenAccount,
+          creatorTokenAccount,
+          client: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([client])
+        .rpc();
+
+    try {
+      await reclaim();
+      expect.fail("cannot reclaim while a submission awaits review");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("MilestonePendingReview");
+    }
+
+    await program.methods
+      .objectMilestone(0)
+      .accounts({ invoice: pda, authority: client.publicKey })
+      .signers([client])
+      .rpc();
+
+    const before = await getAccount(provider.connection, clientTokenAccount);
+    await reclaim();
+    const after = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(4_000_000);
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ cancelled: {} });
+  });
+
+  it("Releases part of a milestone by amount or basis points", async () => {
+    const partialInvoiceId = "INV-PARTIAL-001";
+    const pda = findInvoicePda(partialInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        partialInvoiceId,
+        new anchor.BN(10_000_000),
+        mint,
+        dueDate,
+        "Incremental delivery",
+        [milestone("Pages", 10_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .fundEscrow(new anchor.BN(10_000_000))
+      .accounts({
+        invoice: pda,
+        ...findEscrowPdas(pda),
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const releaseAccounts = {
+      invoice: pda,
+      ...findEscrowPdas(pda),
+      creatorTokenAccount,
+      authority: client.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    await program.methods
+      .releasePartial(0, { bps: [2500] })
+      .accounts(releaseAccounts)
+      .signers([client])
+      .rpc();
+    await program.methods
+      .releasePartial(0, { amount: [new anchor.BN(1_500_000)] })
+      .accounts(releaseAccounts)
+      .signers([client])
+      .rpc();
+
+    let invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.milestones[0].amountReleased.toNumber()).to.equal(4_000_000);
+    expect(invoice.milestones[0].completed).to.equal(false);
+
+    try {
+      await program.methods
+        .releasePartial(0, { amount: [new anchor.BN(6_000_001)] })
+        .accounts(releaseAccounts)
+        .signers([client])
+        .rpc();
+      expect.fail("cannot release more than the milestone holds");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidReleaseAmount");
+    }
+
+    // A full release pays out only what's left
+    const before = await getAccount(provider.connection, creatorTokenAccount);
+    await program.methods
+      .releaseMilestone(0)
+      .accounts(releaseAccounts)
+      .signers([client])
+      .rpc();
+    const after = await getAccount(provider.connection, creatorTokenAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(6_000_000);
+
+    invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.milestones[0].completed).to.equal(true);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+  });
+
+  it("Amends a funded escrow through an accepted change order", async () => {
+    const changeInvoiceId = "INV-CHANGE-001";
+    const pda = findInvoicePda(changeInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        changeInvoiceId,
+        new anchor.BN(6_000_000),
+        mint,
+        dueDate,
+        "Growing scope",
+        [milestone("Design", 2_000_000), milestone("Build", 4_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(6_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    await program.methods
+      .releaseMilestone(0)
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        creatorTokenAccount,
+        authority: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+
+    const [changeOrder] = PublicKey.findProgramAddressSync(
+      [Buffer.from("change_order"), pda.toBuffer()],
+      program.programId
+    );
+    const [changeOrderVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("change_order_vault"), pda.toBuffer()],
+      program.programId
+    );
+
+    // Build is re-priced and a Launch milestone added: 2M + 5M + 1M
+    await program.methods
+      .proposeChangeOrder([milestone("Build", 5_000_000), milestone("Launch", 1_000_000)])
+      .accounts({
+        invoice: pda,
+        changeOrder,
+        changeOrderVault,
+        tokenMint: mint,
+        ...escrowPdas,
+        clientTokenAccount,
+        proposer: creator.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const acceptAs = (authority: PublicKey, signers: Keypair[]) =>
+      program.methods
+        .acceptChangeOrder()
+        .accounts({
+          invoice: pda,
+          changeOrder,
+          changeOrderVault,
+          ...escrowPdas,
+          clientTokenAccount,
+          proposer: creator.publicKey,
+          authority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers(signers)
+        .rpc();
+
+    try {
+      await acceptAs(creator.publicKey, []);
+      expect.fail("the proposer cannot accept their own change order");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+
+    await acceptAs(client.publicKey, [client]);
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.amount.toNumber()).to.equal(8_000_000);
+    expect(invoice.milestones.map((m) => m.description)).to.deep.equal([
+      "Design",
+      "Build",
+      "Launch",
+    ]);
+    expect(invoice.currentMilestone).to.equal(1);
+
+    const vault = await getAccount(provider.connection, escrowPdas.escrowTokenAccount);
+    expect(Number(vault.amount)).to.equal(6_000_000);
+    expect(await provider.connection.getAccountInfo(changeOrder)).to.equal(null);
+    expect(await provider.connection.getAccountInfo(changeOrderVault)).to.equal(null);
+
+    // A client's top-up waits outside the escrow until the order settles
+    const clientBefore = await getAccount(provider.connection, clientTokenAccount);
+    await program.methods
+      .proposeChangeOrder([milestone("Build", 5_000_000), milestone("Launch", 2_000_000)])
+      .accounts({
+        invoice: pda,
+        changeOrder,
+        changeOrderVault,
+        tokenMint: mint,
+        ...escrowPdas,
+        clientTokenAccount,
+        proposer: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const pending = await getAccount(provider.connection, changeOrderVault);
+    expect(Number(pending.amount)).to.equal(1_000_000);
+    const untouched = await getAccount(provider.connection, escrowPdas.escrowTokenAccount);
+    expect(Number(untouched.amount)).to.equal(6_000_000);
+
+    await program.methods
+      .cancelChangeOrder()
+      .accounts({
+        invoice: pda,
+        changeOrder,
+        changeOrderVault,
+        clientTokenAccount,
+        proposer: client.publicKey,
+        authority: creator.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const clientAfter = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(clientAfter.amount)).to.equal(Number(clientBefore.amount));
+    expect(await provider.connection.getAccountInfo(changeOrderVault)).to.equal(null);
+  });
+
+  it("Holds back retainage until the client's final acceptance", async () => {
+    const retainageInvoiceId = "INV-RETAIN-001";
+    const pda = findInvoicePda(retainageInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        retainageInvoiceId,
+        new anchor.BN(10_000_000),
+        mint,
+        dueDate,
+        "Renovation",
+        [milestone("Framing", 4_000_000), milestone("Finishing", 6_000_000)],
+        { ...defaultTerms, retainageBps: 1000, warrantyPeriod: new anchor.BN(86400 * 90) }
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .fundEscrow(new anchor.BN(10_000_000))
+      .accounts({
+        invoice: pda,
+        ...findEscrowPdas(pda),
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const accountsFor = (authority: PublicKey) => ({
+      invoice: pda,
+      ...findEscrowPdas(pda),
+      creatorTokenAccount,
+      authority,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    });
+
+    const before = await getAccount(provider.connection, creatorTokenAccount);
+    for (const index of [0, 1]) {
+      await program.methods
+        .releaseMilestone(index)
+        .accounts(accountsFor(client.publicKey))
+        .signers([client])
+        .rpc();
+    }
+    const afterReleases = await getAccount(provider.connection, creatorTokenAccount);
+    expect(Number(afterReleases.amount) - Number(before.amount)).to.equal(9_000_000);
+
+    let invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+    expect(invoice.retainageHeld.toNumber()).to.equal(1_000_000);
+
+    try {
+      await program.methods
+        .releaseRetainage()
+        .accounts(accountsFor(creator.publicKey))
+        .rpc();
+      expect.fail("creator must wait out the warranty period");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("WarrantyPeriodActive");
+    }
+
+    await program.methods
+      .releaseRetainage()
+      .accounts(accountsFor(client.publicKey))
+      .signers([client])
+      .rpc();
+
+    const afterRetainage = await getAccount(provider.connection, creatorTokenAccount);
+    expect(Number(afterRetainage.amount) - Number(before.amount)).to.equal(10_000_000);
+    invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.retainageHeld.toNumber()).to.equal(0);
+  });
+
+  it("Funds escrow in tranches and blocks releases it cannot cover", async () => {
+    const trancheInvoiceId = "INV-TRANCHE-001";
+    const pda = findInvoicePda(trancheInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        trancheInvoiceId,
+        new anchor.BN(8_000_000),
+        mint,
+        dueDate,
+        "Pay as we go",
+        [milestone("Phase 1", 3_000_000), milestone("Phase 2", 5_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(3_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const release = (index: number) =>
+      program.methods
+        .releaseMilestone(index)
+        .accounts({
+          invoice: pda,
+          ...escrowPdas,
+          creatorTokenAccount,
+          authority: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([client])
+        .rpc();
+
+    try {
+      await release(1);
+      expect.fail("phase 2 is not funded yet");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InsufficientEscrowFunding");
+    }
+    await release(0);
+
+    // Only the outstanding 5M is pulled
+    const before = await getAccount(provider.connection, clientTokenAccount);
+    await program.methods
+      .topUpEscrow(new anchor.BN(9_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+    const after = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(before.amount) - Number(after.amount)).to.equal(5_000_000);
+
+    await release(1);
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+  });
+
+  it("Refunds a crowdfunded escrow pro-rata to its contributors", async () => {
+    const crowdInvoiceId = "INV-CROWD-001";
+    const pda = findInvoicePda(crowdInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    const backer = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(backer.publicKey, LAMPORTS_PER_SOL);
+    await provider.connection.confirmTransaction(sig);
+    const backerTokenAccount = await createAccount(
+      provider.connection,
+      payer,
+      mint,
+      backer.publicKey
+    );
+    await mintTo(provider.connection, payer, mint, backerTokenAccount, creator.publicKey, 8_000_000);
+
+    await program.methods
+      .createInvoice(
+        crowdInvoiceId,
+        new anchor.BN(10_000_000),
+        mint,
+        dueDate,
+        "Consortium project",
+        [milestone("Study", 10_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(4_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const [contribution] = PublicKey.findProgramAddressSync(
+      [Buffer.from("contribution"), pda.toBuffer(), backer.publicKey.toBuffer()],
+      program.programId
+    );
+
+    const contributionAccounts = {
+      invoice: pda,
+      ...escrowPdas,
+      contribution,
+      contributorTokenAccount: backerTokenAccount,
+      contributor: backer.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    };
+    await program.methods
+      .contributeEscrow(new anchor.BN(2_000_000))
+      .accounts(contributionAccounts)
+      .signers([backer])
+      .rpc();
+
+    try {
+      await program.methods
+        .closeContribution()
+        .accounts({ invoice: pda, contribution, contributor: backer.publicKey })
+        .signers([backer])
+        .rpc();
+      expect.fail("a live contribution cannot be closed");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ContributionRefundable");
+    }
+
+    // Only the outstanding 4M of the top-up is taken
+    await program.methods
+      .topUpContribution(new anchor.BN(6_000_000))
+      .accounts(contributionAccounts)
+      .signers([backer])
+      .rpc();
+
+    const clientBefore = await getAccount(provider.connection, clientTokenAccount);
+    await program.methods
+      .cancelFundedEscrow()
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        creatorTokenAccount,
+        creator: creator.publicKey,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+    const clientAfter = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(clientAfter.amount) - Number(clientBefore.amount)).to.equal(4_000_000);
+
+    let invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.refundPool.toNumber()).to.equal(6_000_000);
+    expect(invoice.refundsOutstanding).to.equal(1);
+
+    await program.methods
+      .claimRefund()
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        contribution,
+        contributorTokenAccount: backerTokenAccount,
+        contributor: backer.publicKey,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([backer])
+      .rpc();
+
+    const backerAccount = await getAccount(provider.connection, backerTokenAccount);
+    expect(Number(backerAccount.amount)).to.equal(8_000_000);
+    invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.refundsOutstanding).to.equal(0);
+    expect(await provider.connection.getAccountInfo(escrowPdas.escrow)).to.equal(null);
+  });
+
+  it("Splits an invoice between debtors who each pay their share", async () => {
+    const splitInvoiceId = "INV-SPLIT-001";
+    const pda = findInvoicePda(splitInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    const roommate = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(roommate.publicKey, LAMPORTS_PER_SOL);
+    await provider.connection.confirmTransaction(sig);
+    const roommateTokenAccount = await createAccount(
+      provider.connection,
+      payer,
+      mint,
+      roommate.publicKey
+    );
+    await mintTo(provider.connection, payer, mint, roommateTokenAccount, creator.publicKey, 1_000_000);
+
+    await program.methods
+      .createInvoice(splitInvoiceId, new anchor.BN(3_000_000), mint, dueDate, "Utilities", [], defaultTerms)
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const findShare = (debtor: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("share"), pda.toBuffer(), debtor.toBuffer()],
+        program.programId
+      )[0];
+
+    for (const [debtor, amount] of [
+      [client.publicKey, 2_000_000],
+      [roommate.publicKey, 1_000_000],
+    ] as [PublicKey, number][]) {
+      await program.methods
+        .addDebtorShare(debtor, new anchor.BN(amount))
+        .accounts({
+          invoice: pda,
+          share: findShare(debtor),
+          creator: creator.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    const payShare = (debtor: Keypair, debtorTokenAccount: PublicKey) =>
+      program.methods
+        .payShare()
+        .accounts({
+          invoice: pda,
+          share: findShare(debtor.publicKey),
+          debtorTokenAccount,
+          creatorTokenAccount,
+          debtor: debtor.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([debtor])
+        .rpc();
+
+    await payShare(client, clientTokenAccount);
+    let invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ pending: {} });
+    expect(invoice.sharesPaid).to.equal(1);
+
+    const clientShare = await program.account.debtorShare.fetch(findShare(client.publicKey));
+    expect(clientShare.paid).to.equal(true);
+
+    try {
+      await payShare(client, clientTokenAccount);
+      expect.fail("a share can only be paid once");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ShareAlreadyPaid");
+    }
+
+    try {
+      await program.methods
+        .cancelInvoice()
+        .accounts({ invoice: pda, creator: creator.publicKey })
+        .rpc();
+      expect.fail("cannot cancel once a share is paid");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("SharesPartlyPaid");
+    }
+
+    await payShare(roommate, roommateTokenAccount);
+    invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+  });
+
+  it("Records deliverable hashes and URIs on submitted milestones", async () => {
+    const deliverableInvoiceId = "INV-DELIVER-001";
+    const pda = findInvoicePda(deliverableInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        deliverableInvoiceId,
+        new anchor.BN(2_000_000),
+        mint,
+        dueDate,
+        "Audit report",
+        [milestone("Report", 2_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .fundEscrow(new anchor.BN(2_000_000))
+      .accounts({
+        invoice: pda,
+        ...findEscrowPdas(pda),
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const submit = (uri: string) =>
+      program.methods
+        .submitMilestone(0, deliverableHash, uri)
+        .accounts({
+          invoice: pda,
+          creator: creator.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+
+    const deliverableHash = Array.from(createHash("sha256").update("audit.pdf").digest());
+    try {
+      await submit("ar://" + "x".repeat(200));
+      expect.fail("URI is too long");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("DeliverableUriTooLong");
+    }
+
+    const sizeBefore = (await provider.connection.getAccountInfo(pda)).data.length;
+    const uri = "ar://audit-report-v1";
+    await submit(uri);
+
+    const sizeAfter = (await provider.connection.getAccountInfo(pda)).data.length;
+    expect(sizeAfter - sizeBefore).to.equal(uri.length);
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.milestones[0].deliverableUri).to.equal(uri);
+    expect(invoice.milestones[0].deliverableHash).to.deep.equal(deliverableHash);
+    expect(invoice.milestones[0].submittedAt.toNumber()).to.be.greaterThan(0);
+  });
+
+  it("Deposits idle escrow into a yield strategy and splits the yield", async () => {
+    const yieldInvoiceId = "INV-YIELD-001";
+    const pda = findInvoicePda(yieldInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    // 1% flat yield, paid out of a reserve minted into the pool vault
+    const [yieldPool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), mint.toBuffer()],
+      yieldProgram.programId
+    );
+    const [yieldVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool_vault"), mint.toBuffer()],
+      yieldProgram.programId
+    );
+    await yieldProgram.methods
+      .initializePool(100)
+      .accounts({
+        pool: yieldPool,
+        poolVault: yieldVault,
+        mint,
+        payer: creator.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    await mintTo(provider.connection, payer, mint, yieldVault, creator.publicKey, 1_000_000);
+
+    // The upgrade authority (the test wallet) administers the whitelist
+    const [yieldConfig] = PublicKey.findProgramAddressSync(
+      [Buffer.from("yield_config")],
+      program.programId
+    );
+    const [programData] = PublicKey.findProgramAddressSync(
+      [program.programId.toBuffer()],
+      new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
+    await program.methods
+      .initializeYieldConfig()
+      .accounts({
+        yieldConfig,
+        program: program.programId,
+        programData,
+        admin: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const createYieldInvoice = () =>
+      program.methods
+        .createInvoice(
+          yieldInvoiceId,
+          new anchor.BN(4_000_000),
+          mint,
+          dueDate,
+          "Long engagement",
+          [milestone("Design", 2_000_000), milestone("Delivery", 2_000_000)],
+          { ...defaultTerms, yieldProgram: yieldProgram.programId, yieldCreatorBps: 2500 }
+        )
+        .accounts({
+          invoice: pda,
+          yieldConfig,
+          creator: creator.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+
+    try {
+      await createYieldInvoice();
+      expect.fail("the mock is not whitelisted yet");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("YieldProgramNotWhitelisted");
+    }
+
+    await program.methods
+      .addYieldProgram(yieldProgram.programId)
+      .accounts({ yieldConfig, admin: creator.publicKey })
+      .rpc();
+    await createYieldInvoice();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(4_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const strategyAccounts = {
+      yieldProgram: yieldProgram.programId,
+      yieldPool,
+      yieldVault,
+      yieldPosition: PublicKey.findProgramAddressSync(
+        [Buffer.from("position"), yieldPool.toBuffer(), escrowPdas.escrow.toBuffer()],
+        yieldProgram.programId
+      )[0],
+    };
+    const deposit = (amount: number) =>
+      program.methods
+        .depositToStrategy(new anchor.BN(amount))
+        .accounts({
+          invoice: pda,
+          yieldConfig,
+          ...escrowPdas,
+          ...strategyAccounts,
+          client: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([client])
+        .rpc();
+    const release = (index: number) =>
+      program.methods
+        .releaseMilestone(index)
+        .accounts({
+          invoice: pda,
+          ...escrowPdas,
+          creatorTokenAccount,
+          authority: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([client]);
+    await deposit(4_000_000);
+
+    try {
+      await release(0).rpc();
+      expect.fail("pulling the funds back needs the strategy accounts");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("StrategyFundsDeployed");
+    }
+
+    // Releasing pulls the deposit back itself: 40k of yield, a quarter of it
+    // to the creator, on top of the first milestone
+    let creatorBefore = await getAccount(provider.connection, creatorTokenAccount);
+    let clientBefore = await getAccount(provider.connection, clientTokenAccount);
+    await release(0)
+      .remainingAccounts(
+        [
+          strategyAccounts.yieldProgram,
+          yieldPool,
+          yieldVault,
+          strategyAccounts.yieldPosition,
+          creatorTokenAccount,
+          clientTokenAccount,
+          client.publicKey,
+        ].map((pubkey, i) => ({ pubkey, isSigner: false, isWritable: i >= 2 }))
+      )
+      .rpc();
+
+    let creatorAfter = await getAccount(provider.connection, creatorTokenAccount);
+    let clientAfter = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(creatorAfter.amount) - Number(creatorBefore.amount)).to.equal(2_010_000);
+    expect(Number(clientAfter.amount) - Number(clientBefore.amount)).to.equal(30_000);
+    let invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.yieldDeposited.toNumber()).to.equal(0);
+
+    // The rest can still be pulled back on its own
+    await deposit(2_000_000);
+    creatorBefore = await getAccount(provider.connection, creatorTokenAccount);
+    clientBefore = await getAccount(provider.connection, clientTokenAccount);
+    await program.methods
+      .withdrawFromStrategy()
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        ...strategyAccounts,
+        creatorTokenAccount,
+        clientTokenAccount,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    creatorAfter = await getAccount(provider.connection, creatorTokenAccount);
+    clientAfter = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(creatorAfter.amount) - Number(creatorBefore.amount)).to.equal(5_000);
+    expect(Number(clientAfter.amount) - Number(clientBefore.amount)).to.equal(15_000);
+
+    await release(1).rpc();
+    invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+    expect(invoice.yieldDeposited.toNumber()).to.equal(0);
+  });
+
+  it("Holds direct payments open to chargebacks the arbiter settles", async () => {
+    const holdInvoiceId = "INV-HOLD-001";
+    const pda = findInvoicePda(holdInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        holdInvoiceId,
+        new anchor.BN(2_000_000),
+        mint,
+        dueDate,
+        "Marketplace order",
+        [],
+        {
+          ...defaultTerms,
+          arbiter: arbiter.publicKey,
+          arbiterFeeBps: 500,
+          holdPeriod: new anchor.BN(86400 * 14),
+        }
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    try {
+      await program.methods
+        .markPaid("sig")
+        .accounts({ invoice: pda, payer: client.publicKey })
+        .signers([client])
+        .rpc();
+      expect.fail("held invoices are paid through the vault");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("HoldRequired");
+    }
+
+    const now = Math.floor(Date.now() / 1000);
+    try {
+      await program.methods
+        .fundStream(new anchor.BN(now), new anchor.BN(now + 3600))
+        .accounts({
+          invoice: pda,
+          ...findStreamPdas(pda),
+          clientTokenAccount,
+          tokenMint: mint,
+          client: client.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([client])
+        .rpc();
+      expect.fail("held invoices cannot be streamed");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("HoldRequired");
+    }
+
+    const [hold] = PublicKey.findProgramAddressSync(
+      [Buffer.from("hold"), pda.toBuffer()],
+      program.programId
+    );
+    const [holdTokenAccount] = PublicKey.findProgramAddressSync(
+      [Buffer.from("hold_vault"), pda.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .payWithHold()
+      .accounts({
+        invoice: pda,
+        hold,
+        holdTokenAccount,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const withdraw = () =>
+      program.methods
+        .withdrawAfterHold()
+        .accounts({
+          invoice: pda,
+          hold,
+          holdTokenAccount,
+          creatorTokenAccount,
+          client: client.publicKey,
+          creator: creator.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+    try {
+      await withdraw();
+      expect.fail("hold period has not ended");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("HoldPeriodActive");
+    }
+
+    await program.methods
+      .requestChargeback(3, Array(32).fill(7))
+      .accounts({ invoice: pda, hold, client: client.publicKey })
+      .signers([client])
+      .rpc();
+    try {
+      await withdraw();
+      expect.fail("chargeback is pending");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ChargebackPending");
+    }
+
+    // 5% fee on 2M leaves 1.9M: 1.2M back to the client, 0.7M to the creator
+    const creatorBefore = await getAccount(provider.connection, creatorTokenAccount);
+    const clientBefore = await getAccount(provider.connection, clientTokenAccount);
+    await program.methods
+      .resolveChargeback(new anchor.BN(1_200_000))
+      .accounts({
+        invoice: pda,
+        hold,
+        holdTokenAccount,
+        creatorTokenAccount,
+        clientTokenAccount,
+        arbiterTokenAccount,
+        client: client.publicKey,
+        authority: arbiter.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([arbiter])
+      .rpc();
+
+    const creatorAfter = await getAccount(provider.connection, creatorTokenAccount);
+    const clientAfter = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(creatorAfter.amount) - Number(creatorBefore.amount)).to.equal(700_000);
+    expect(Number(clientAfter.amount) - Number(clientBefore.amount)).to.equal(1_200_000);
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ resolved: {} });
+    expect(await provider.connection.getAccountInfo(hold)).to.be.null;
+  });
+
+  it("Vests direct payments to the creator after a cliff", async () => {
+    const vestingInvoiceId = "INV-VEST-001";
+    const pda = findInvoicePda(vestingInvoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        vestingInvoiceId,
+        new anchor.BN(3_000_000),
+        mint,
+        dueDate,
+        "Contributor grant",
+        [],
+        { ...defaultTerms, vestingCliff: new anchor.BN(2), vestingDuration: new anchor.BN(4) }
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const [vesting] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vesting"), pda.toBuffer()],
+      program.programId
+    );
+    const [vestingTokenAccount] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vesting_vault"), pda.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .payWithVesting()
+      .accounts({
+        invoice: pda,
+        vesting,
+        vestingTokenAccount,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const claim = () =>
+      program.methods
+        .claimVested()
+        .accounts({
+          invoice: pda,
+          vesting,
+          vestingTokenAccount,
+          creatorTokenAccount,
+          client: client.publicKey,
+          creator: creator.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+    try {
+      await claim();
+      expect.fail("cliff has not passed");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("NothingVested");
+    }
+
+    await new Promise((resolve) => setTimeout(resolve, 5000));
+    const before = await getAccount(provider.connection, creatorTokenAccount);
+    await claim();
+    const after = await getAccount(provider.connection, creatorTokenAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(3_000_000);
+
+    // Fully claimed vestings are closed
+    expect(await provider.connection.getAccountInfo(vesting)).to.be.null;
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.status).to.deep.equal({ paid: {} });
+  });
+
+  it("Pays out earned retainage before refunding a cancelled escrow", async () => {
+    const invoiceId = "INV-RETAIN-CANCEL";
+    const pda = findInvoicePda(invoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    await program.methods
+      .createInvoice(
+        invoiceId,
+        new anchor.BN(10_000_000),
+        mint,
+        dueDate,
+        "Renovation",
+        [milestone("Framing", 4_000_000), milestone("Finishing", 6_000_000)],
+        { ...defaultTerms, retainageBps: 1000 }
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(10_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    await program.methods
+      .releaseMilestone(0)
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        creatorTokenAccount,
+        authority: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+
+    const creatorBefore = await getAccount(provider.connection, creatorTokenAccount);
+    const clientBefore = await getAccount(provider.connection, clientTokenAccount);
+    await program.methods
+      .cancelFundedEscrow()
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        creatorTokenAccount,
+        creator: creator.publicKey,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+
+    // 10% of Framing was retained; only Finishing goes back to the client
+    const creatorAfter = await getAccount(provider.connection, creatorTokenAccount);
+    const clientAfter = await getAccount(provider.connection, clientTokenAccount);
+    expect(Number(creatorAfter.amount) - Number(creatorBefore.amount)).to.equal(400_000);
+    expect(Number(clientAfter.amount) - Number(clientBefore.amount)).to.equal(6_000_000);
+
+    const invoice = await program.account.invoice.fetch(pda);
+    expect(invoice.retainageHeld.toNumber()).to.equal(0);
+  });
+
+  it("Returns a settled contribution's rent once the invoice is paid", async () => {
+    const invoiceId = "INV-CROWD-PAID";
+    const pda = findInvoicePda(invoiceId);
+    const dueDate = new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30);
+
+    const backer = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(backer.publicKey, LAMPORTS_PER_SOL);
+    await provider.connection.confirmTransaction(sig);
+    const backerTokenAccount = await createAccount(
+      provider.connection,
+      payer,
+      mint,
+      backer.publicKey
+    );
+    await mintTo(provider.connection, payer, mint, backerTokenAccount, creator.publicKey, 1_000_000);
+
+    await program.methods
+      .createInvoice(
+        invoiceId,
+        new anchor.BN(2_000_000),
+        mint,
+        dueDate,
+        "Shared tooling",
+        [milestone("Tooling", 2_000_000)],
+        defaultTerms
+      )
+      .accounts({
+        invoice: pda,
+        creator: creator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    await program.methods
+      .fundEscrow(new anchor.BN(1_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount,
+        tokenMint: mint,
+        client: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([client])
+      .rpc();
+
+    const [contribution] = PublicKey.findProgramAddressSync(
+      [Buffer.from("contribution"), pda.toBuffer(), backer.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .contributeEscrow(new anchor.BN(1_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        contribution,
+        contributorTokenAccount: backerTokenAccount,
+        contributor: backer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([backer])
+      .rpc();
+
+    await program.methods
+      .releaseMilestone(0)
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        creatorTokenAccount,
+        authority: client.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([client])
+      .rpc();
+
+    await program.methods
+      .closeContribution()
+      .accounts({ invoice: pda, contribution, contributor: backer.publicKey })
+      .signers([backer])
+      .rpc();
+    expect(await provider.connection.getAccountInfo(contribution)).to.equal(null);
+  });
+
+  it("Keeps a legacy escrow from being claimed by another creator's invoice", async () => {
+    // Fixtures: INV-LEGACY-001's escrow funded under the old `invoice_id`-only seeds
+    const legacyInvoiceId = "INV-LEGACY-001";
+    const legacyMint = new PublicKey("DfbkhytZn37dzxQVYhtA2ZngV12WihR9NR77DirQLWMg");
+    const legacyEscrow = new PublicKey("CFV1xDfYwe5Qu9vSvnMC4nSChq2sxqGsopLabepHPMhB");
+    const legacyEscrowTokenAccount = new PublicKey(
+      "DwcmSsNBtV5mMcKU7VVnV4PetsBfx6aTLuz2n4XMpNjP"
+    );
+    const mintAuthority = Keypair.fromSecretKey(Uint8Array.from(legacyMintAuthority));
+
+    // A second creator reuses the id and funds their own escrow in the same mint
+    const intruder = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(intruder.publicKey, LAMPORTS_PER_SOL);
+    await provider.connection.confirmTransaction(sig);
+    const intruderTokenAccount = await createAccount(
+      provider.connection,
+      payer,
+      legacyMint,
+      intruder.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      payer,
+      legacyMint,
+      intruderTokenAccount,
+      mintAuthority,
+      1_000_000
+    );
+
+    const [pda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("invoice"), intruder.publicKey.toBuffer(), Buffer.from(legacyInvoiceId)],
+      program.programId
+    );
+    await program.methods
+      .createInvoice(
+        legacyInvoiceId,
+        new anchor.BN(1_000_000),
+        legacyMint,
+        new anchor.BN(Math.floor(Date.now() / 1000) + 86400 * 30),
+        "Same id, different creator",
+        [milestone("Anything", 1_000_000)],
+        { ...defaultTerms, client: intruder.publicKey }
+      )
+      .accounts({
+        invoice: pda,
+        creator: intruder.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([intruder])
+      .rpc();
+
+    const escrowPdas = findEscrowPdas(pda);
+    const migrate = () =>
+      program.methods
+        .migrateEscrow()
+        .accounts({
+          invoice: pda,
+          legacyEscrow,
+          legacyEscrowTokenAccount,
+          ...escrowPdas,
+          tokenMint: legacyMint,
+          client: intruder.publicKey,
+          authority: intruder.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([intruder])
+        .rpc();
+
+    try {
+      await migrate();
+      expect.fail("an unfunded invoice cannot claim the legacy escrow");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidInvoiceStatus");
+    }
+
+    // Funded under the new seeds, it already has an escrow of its own
+    await program.methods
+      .fundEscrow(new anchor.BN(1_000_000))
+      .accounts({
+        invoice: pda,
+        ...escrowPdas,
+        clientTokenAccount: intruderTokenAccount,
+        tokenMint: legacyMint,
+        client: intruder.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([intruder])
+      .rpc();
+
+    let migrated = true;
+    try {
+      await migrate();
+    } catch (err) {
+      migrated = false;
+    }
+    expect(migrated).to.equal(false);
+
+    const legacyVault = await getAccount(provider.connection, legacyEscrowTokenAccount);
+    expect(Number(legacyVault.amount)).to.equal(5_000_000);
+    expect(await provider.connection.getAccountInfo(legacyEscrow)).to.not.equal(null);
+  });
 });